@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS milk_buckets (
+    key TEXT PRIMARY KEY,
+    tokens INT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
\ No newline at end of file
@@ -1,20 +1,36 @@
 use std::{
+    collections::HashMap,
+    convert::Infallible,
     fmt::{Display, Write},
+    pin::pin,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    async_trait,
+    body::Bytes,
+    extract::{
+        ws::{Message, WebSocketUpgrade},
+        FromRequestParts, Path, Query, State,
+    },
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::{get, post},
-    Router,
+    Json, Router,
+};
+use futures_util::{
+    future::{select, Either},
+    stream, Stream, StreamExt,
 };
 use rand::{rngs::StdRng, Rng, SeedableRng};
-use serde::Deserialize;
-
-type Position = (usize, usize);
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 enum Tile {
     Empty,
     Cookie,
@@ -30,6 +46,15 @@ impl Display for Tile {
         f.write_char(c)
     }
 }
+impl Tile {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Empty => "empty",
+            Self::Cookie => "cookie",
+            Self::Milk => "milk",
+        }
+    }
+}
 #[derive(Clone, Copy)]
 enum DoneState {
     Cookie,
@@ -47,23 +72,69 @@ impl Display for DoneState {
     }
 }
 
+const MAX_SIDE: usize = 64;
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default)]
+struct GameConfig {
+    width: usize,
+    height: usize,
+    win_length: usize,
+    // out-of-turn moves are a 409 when set
+    enforce_turns: bool,
+}
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            width: 4,
+            height: 4,
+            win_length: 4,
+            enforce_turns: true,
+        }
+    }
+}
+impl GameConfig {
+    fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_SIDE).contains(&self.width) || !(1..=MAX_SIDE).contains(&self.height) {
+            return Err(format!("width and height must be between 1 and {MAX_SIDE}"));
+        }
+        // a longer run would not fit on the board in any direction
+        if !(1..=self.width.max(self.height)).contains(&self.win_length) {
+            return Err("win_length must fit on the board".to_string());
+        }
+        Ok(())
+    }
+}
+
 pub struct Game {
-    board: [[Tile; 4]; 4],
+    config: GameConfig,
+    // columns, bottom tile first
+    board: Vec<Vec<Tile>>,
     is_done: Option<DoneState>,
     rng: StdRng,
+    next_player: Tile,
+    move_count: usize,
+    // teams that joined, only the holder of the token may place for them
+    players: HashMap<Tile, String>,
+    // pinged after every change, subscribers render the board themselves so a
+    // lagging one just skips to the latest board
+    updates: broadcast::Sender<()>,
 }
 
 impl Display for Game {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let w = '⬜';
-        for y in (0..4).rev() {
+        for y in (0..self.config.height).rev() {
             f.write_char(w)?;
-            for x in 0..4 {
+            for x in 0..self.config.width {
                 f.write_fmt(format_args!("{}", self.get_tile(x, y)))?
             }
             f.write_fmt(format_args!("{w}\n"))?
         }
-        f.write_fmt(format_args!("{w}{w}{w}{w}{w}{w}\n"))?;
+        for _ in 0..self.config.width + 2 {
+            f.write_char(w)?;
+        }
+        f.write_char('\n')?;
 
         if let Some(state) = self.is_done {
             f.write_fmt(format_args!("{state}\n"))?;
@@ -74,37 +145,65 @@ impl Display for Game {
 }
 
 impl Game {
-    fn new() -> Self {
-        let board = [[Tile::Empty; 4]; 4];
+    fn new(config: GameConfig) -> Self {
+        let board = vec![vec![Tile::Empty; config.height]; config.width];
         let is_done = None;
         let rng = StdRng::seed_from_u64(2024);
         Self {
+            config,
             board,
             is_done,
             rng,
+            next_player: Tile::Cookie,
+            move_count: 0,
+            players: HashMap::new(),
+            updates: broadcast::channel(16).0,
         }
     }
 
+    // a fresh board for the same players and subscribers
+    fn reset(&mut self) {
+        let players = std::mem::take(&mut self.players);
+        let updates = self.updates.clone();
+        *self = Game::new(self.config);
+        self.players = players;
+        self.updates = updates;
+        self.publish();
+    }
+
+    fn publish(&self) {
+        // no subscribers is not an error
+        let _ = self.updates.send(());
+    }
+
     fn get_tile(&self, x: usize, y: usize) -> Tile {
         self.board[x][y]
     }
 
+    // whether (x, y) is part of a run of at least `win_length` equal tiles
     fn test_win(&self, x: usize, y: usize) -> bool {
         let tile = self.get_tile(x, y);
-        let t2b = [(x, 0), (x, 1), (x, 2), (x, 3)];
-        let l2r = [(0, y), (1, y), (2, y), (3, y)];
-        let l_d = [(0, 3), (1, 2), (2, 1), (3, 0)];
-        let r_d = [(0, 0), (1, 1), (2, 2), (3, 3)];
-
-        let t = |pos: &[Position]| -> bool {
-            pos.contains(&(x, y))
-                && pos
-                    .iter()
-                    .map(|(x, y)| self.get_tile(*x, *y))
-                    .all(|t| t == tile)
+        let run = |dx: isize, dy: isize| {
+            let (mut x, mut y) = (x, y);
+            let mut n = 0;
+            loop {
+                let (Some(nx), Some(ny)) = (x.checked_add_signed(dx), y.checked_add_signed(dy))
+                else {
+                    break n;
+                };
+                if nx >= self.config.width || ny >= self.config.height {
+                    break n;
+                }
+                if self.get_tile(nx, ny) != tile {
+                    break n;
+                }
+                (x, y, n) = (nx, ny, n + 1);
+            }
         };
 
-        t(&t2b) || t(&l2r) || t(&l_d) || t(&r_d)
+        [(1, 0), (0, 1), (1, 1), (1, -1)]
+            .iter()
+            .any(|&(dx, dy)| 1 + run(dx, dy) + run(-dx, -dy) >= self.config.win_length)
     }
 
     fn do_step(&mut self, column: usize, tile: Tile) -> bool {
@@ -122,6 +221,11 @@ impl Game {
             None => return false,
         };
         *find_tile = tile;
+        self.move_count += 1;
+        self.next_player = match tile {
+            Tile::Cookie => Tile::Milk,
+            _ => Tile::Cookie,
+        };
 
         let is_full = self
             .board
@@ -141,12 +245,14 @@ impl Game {
                 Tile::Empty => unreachable!(),
             });
         }
+        self.publish();
         true
     }
 
     fn do_random(&mut self) {
-        for y in (0..4).rev() {
-            for x in 0..4 {
+        // top row first, so a seeded game always draws the same board
+        for y in (0..self.config.height).rev() {
+            for x in 0..self.config.width {
                 let tile = if self.rng.gen::<bool>() {
                     Tile::Cookie
                 } else {
@@ -155,76 +261,409 @@ impl Game {
                 self.board[x][y] = tile;
             }
         }
+        // every tile counts as placed, turns start over with the next board
+        self.move_count = self.config.width * self.config.height;
+        self.next_player = Tile::Cookie;
 
-        for x in 0..4 {
-            for y in 0..4 {
+        for x in 0..self.config.width {
+            for y in 0..self.config.height {
                 if self.test_win(x, y) {
                     //don't check this
                     self.is_done = Some(DoneState::Cookie);
+                    self.publish();
                     return;
                 }
             }
         }
-        self.is_done = Some(DoneState::Nothing)
+        self.is_done = Some(DoneState::Nothing);
+        self.publish();
+    }
+}
+
+// the board for clients that would rather not parse the emoji rendering
+#[derive(Serialize)]
+struct BoardView {
+    width: usize,
+    height: usize,
+    // rows top first, as rendered
+    grid: Vec<Vec<&'static str>>,
+    done: bool,
+    winner: Option<&'static str>,
+    next_player: &'static str,
+    move_count: usize,
+}
+impl From<&Game> for BoardView {
+    fn from(game: &Game) -> Self {
+        let grid = (0..game.config.height)
+            .rev()
+            .map(|y| {
+                (0..game.config.width)
+                    .map(|x| game.get_tile(x, y).name())
+                    .collect()
+            })
+            .collect();
+        let winner = match game.is_done {
+            Some(DoneState::Cookie) => Some(Tile::Cookie.name()),
+            Some(DoneState::Milk) => Some(Tile::Milk.name()),
+            _ => None,
+        };
+        Self {
+            width: game.config.width,
+            height: game.config.height,
+            grid,
+            done: game.is_done.is_some(),
+            winner,
+            next_player: game.next_player.name(),
+            move_count: game.move_count,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Text,
+    Json,
+}
+impl Format {
+    fn render(self, game: &Game) -> String {
+        match self {
+            Self::Text => game.to_string(),
+            Self::Json => serde_json::to_string(&BoardView::from(game)).unwrap(),
+        }
+    }
+
+    fn respond(self, status: StatusCode, game: &Game) -> Response {
+        match self {
+            Self::Text => (status, game.to_string()).into_response(),
+            Self::Json => (status, Json(BoardView::from(game))).into_response(),
+        }
+    }
+}
+
+// the emoji board unless the Accept header ranks JSON above plain text
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = Infallible;
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(accept) = parts
+            .headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+        else {
+            return Ok(Self::Text);
+        };
+        let (mut json, mut text) = (0.0f32, 0.0f32);
+        for range in accept.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let media = params.next().unwrap_or_default();
+            let q = params
+                .find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            match media {
+                "application/json" | "application/*" => json = json.max(q),
+                "text/plain" | "text/*" | "*/*" => text = text.max(q),
+                _ => {}
+            }
+        }
+        Ok(if json > text { Self::Json } else { Self::Text })
     }
 }
 
 type StdMutex<T> = std::sync::Mutex<T>;
 type GameState = Arc<StdMutex<Game>>;
 
+const GAME_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const MAX_GAMES: usize = 1_000;
+
+struct Entry {
+    game: GameState,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct Games {
+    map: HashMap<String, Entry>,
+}
+impl Games {
+    fn create(&mut self, config: GameConfig) -> String {
+        let now = Instant::now();
+        self.evict(now);
+        self.make_room();
+        let id = loop {
+            let id = format!("{:016x}", rand::random::<u64>());
+            if !self.map.contains_key(&id) {
+                break id;
+            }
+        };
+        let entry = Entry {
+            game: Arc::new(StdMutex::new(Game::new(config))),
+            last_used: now,
+        };
+        self.map.insert(id.clone(), entry);
+        id
+    }
+
+    fn get(&mut self, id: &str) -> Option<GameState> {
+        let now = Instant::now();
+        self.evict(now);
+        let entry = self.map.get_mut(id)?;
+        entry.last_used = now;
+        Some(entry.game.clone())
+    }
+
+    fn evict(&mut self, now: Instant) {
+        self.map
+            .retain(|_, e| now.duration_since(e.last_used) < GAME_IDLE_TIMEOUT);
+    }
+
+    // drop least recently used games until there is room for one more
+    fn make_room(&mut self) {
+        while !self.map.is_empty() && self.map.len() >= MAX_GAMES {
+            let lru = self
+                .map
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone())
+                .unwrap();
+            self.map.remove(&lru);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MyState {
+    // backs the unscoped /12/... routes and never expires
+    default: GameState,
+    games: Arc<StdMutex<Games>>,
+}
+
+// the game named by the `:id` path segment, or the default game
+struct CurrentGame(GameState);
+
+#[async_trait]
+impl FromRequestParts<MyState> for CurrentGame {
+    type Rejection = StatusCode;
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &MyState,
+    ) -> Result<Self, Self::Rejection> {
+        let Path(params) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        match params.get("id") {
+            Some(id) => match state.games.lock().unwrap().get(id) {
+                Some(game) => Ok(Self(game)),
+                None => Err(StatusCode::NOT_FOUND),
+            },
+            None => Ok(Self(state.default.clone())),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct Payload {
     team: String,
     column: usize,
 }
 impl Payload {
-    fn try_into(self) -> Option<(Tile, usize)> {
-        let tile = match &*self.team {
-            "cookie" => Tile::Cookie,
-            "milk" => Tile::Milk,
-            _ => return None,
-        };
-        if !(1..=4).contains(&self.column) {
+    fn try_into(self, width: usize) -> Option<(Tile, usize)> {
+        let tile = parse_team(&self.team)?;
+        if !(1..=width).contains(&self.column) {
             return None;
         }
         Some((tile, self.column - 1))
     }
 }
 
-async fn p1(State(game): State<GameState>) -> String {
-    game.lock().unwrap().to_string()
+fn parse_team(team: &str) -> Option<Tile> {
+    match team {
+        "cookie" => Some(Tile::Cookie),
+        "milk" => Some(Tile::Milk),
+        _ => None,
+    }
 }
-async fn p2(State(game): State<GameState>) -> String {
+
+async fn p1(CurrentGame(game): CurrentGame, format: Format) -> Response {
+    format.respond(StatusCode::OK, &game.lock().unwrap())
+}
+async fn p2(CurrentGame(game): CurrentGame, format: Format) -> Response {
     let mut game = game.lock().unwrap();
-    *game = Game::new();
-    game.to_string()
+    game.reset();
+    format.respond(StatusCode::OK, &game)
 }
 
-async fn p3(State(game): State<GameState>, Path(payload): Path<Payload>) -> (StatusCode, String) {
-    let (tile, colunm) = match payload.try_into() {
+async fn p3(
+    CurrentGame(game): CurrentGame,
+    Path(payload): Path<Payload>,
+    headers: HeaderMap,
+    format: Format,
+) -> Response {
+    let mut game = game.lock().unwrap();
+    let (tile, colunm) = match payload.try_into(game.config.width) {
         Some(p) => p,
-        None => return (StatusCode::BAD_REQUEST, "".to_string()),
+        None => return StatusCode::BAD_REQUEST.into_response(),
     };
 
-    let mut game = game.lock().unwrap();
+    if let Some(token) = game.players.get(&tile) {
+        let given = headers.get(PLAYER_TOKEN).and_then(|v| v.to_str().ok());
+        if given != Some(token.as_str()) {
+            return format.respond(StatusCode::FORBIDDEN, &game);
+        }
+    }
+    if game.config.enforce_turns && game.is_done.is_none() && tile != game.next_player {
+        return format.respond(StatusCode::CONFLICT, &game);
+    }
     if game.do_step(colunm, tile) {
-        (StatusCode::OK, game.to_string())
+        format.respond(StatusCode::OK, &game)
     } else {
-        (StatusCode::SERVICE_UNAVAILABLE, game.to_string())
+        format.respond(StatusCode::SERVICE_UNAVAILABLE, &game)
     }
 }
-async fn p4(State(game): State<GameState>) -> String {
+async fn p4(CurrentGame(game): CurrentGame, format: Format) -> Response {
     let mut game = game.lock().unwrap();
     game.do_random();
-    game.to_string()
+    format.respond(StatusCode::OK, &game)
+}
+
+// the current board, then the board after every change
+fn board_updates(game: GameState, format: Format) -> impl Stream<Item = String> {
+    let rx = game.lock().unwrap().updates.subscribe();
+    stream::unfold((game, rx, true), move |(game, mut rx, first)| async move {
+        if !first {
+            match rx.recv().await {
+                Ok(()) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            }
+        }
+        let board = format.render(&game.lock().unwrap());
+        Some((board, (game, rx, false)))
+    })
+}
+
+// streams are asked for JSON with `?format=json`, their Accept is taken by
+// the transport
+#[derive(Deserialize)]
+struct StreamQuery {
+    #[serde(default)]
+    format: Format,
+}
+
+async fn board_sse(
+    CurrentGame(game): CurrentGame,
+    Query(q): Query<StreamQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = board_updates(game, q.format).map(|board| Ok(Event::default().data(board)));
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn board_ws(
+    CurrentGame(game): CurrentGame,
+    Query(q): Query<StreamQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |mut socket| async move {
+        let mut updates = pin!(board_updates(game, q.format));
+        loop {
+            // also wait on the socket, to notice a client that went away
+            let board = match select(updates.next(), pin!(socket.recv())).await {
+                Either::Left((Some(board), _)) => board,
+                Either::Right((Some(Ok(msg)), _)) if !matches!(msg, Message::Close(_)) => continue,
+                _ => break,
+            };
+            if socket.send(Message::Text(board)).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+const PLAYER_TOKEN: &str = "x-player-token";
+
+#[derive(Deserialize)]
+struct JoinPath {
+    team: String,
+}
+
+#[derive(Serialize)]
+struct Joined {
+    team: String,
+    token: String,
+}
+
+// binds a team to a new token, to be sent as `X-Player-Token` when placing.
+// Only routed for `/12/games/:id`, the shared default board has no owner who
+// could ever unbind a team again.
+async fn join(
+    CurrentGame(game): CurrentGame,
+    Path(path): Path<JoinPath>,
+) -> Result<Json<Joined>, StatusCode> {
+    let tile = parse_team(&path.team).ok_or(StatusCode::BAD_REQUEST)?;
+    let mut game = game.lock().unwrap();
+    if game.players.contains_key(&tile) {
+        return Err(StatusCode::CONFLICT);
+    }
+    let token = format!("{:032x}", rand::random::<u128>());
+    game.players.insert(tile, token.clone());
+    Ok(Json(Joined {
+        team: path.team,
+        token,
+    }))
+}
+
+#[derive(Serialize)]
+struct Created {
+    id: String,
+}
+
+// takes an optional `GameConfig` body, a 4x4 connect four without one
+async fn create(
+    State(state): State<MyState>,
+    bytes: Bytes,
+) -> Result<(StatusCode, [(header::HeaderName, String); 1], Json<Created>), (StatusCode, String)> {
+    let config = if bytes.is_empty() {
+        GameConfig::default()
+    } else {
+        serde_json::from_slice(&bytes).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+    };
+    config
+        .validate()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let id = state.games.lock().unwrap().create(config);
+    let location = format!("/12/games/{id}/board");
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, location)],
+        Json(Created { id }),
+    ))
 }
 
 pub fn router() -> Router {
-    let state = Arc::new(StdMutex::new(Game::new()));
+    let state = MyState {
+        // free placement with no players, as the board has always worked
+        default: Arc::new(StdMutex::new(Game::new(GameConfig {
+            enforce_turns: false,
+            ..GameConfig::default()
+        }))),
+        games: Arc::default(),
+    };
     Router::new()
         .route("/12/board", get(p1))
+        .route("/12/board/stream", get(board_sse))
+        .route("/12/board/ws", get(board_ws))
         .route("/12/reset", post(p2))
         .route("/12/place/:team/:column", post(p3))
         .route("/12/random-board", get(p4))
+        .route("/12/games", post(create))
+        .route("/12/games/:id/board", get(p1))
+        .route("/12/games/:id/board/stream", get(board_sse))
+        .route("/12/games/:id/board/ws", get(board_ws))
+        .route("/12/games/:id/reset", post(p2))
+        .route("/12/games/:id/place/:team/:column", post(p3))
+        .route("/12/games/:id/random-board", get(p4))
+        .route("/12/games/:id/join/:team", post(join))
         .with_state(state)
 }
@@ -1,31 +1,156 @@
 use std::{
-    error::Error, net::{Ipv4Addr, Ipv6Addr}, ops::BitXor, str::FromStr
+    convert::Infallible, error::Error, fmt::Display, net::{IpAddr, Ipv4Addr, Ipv6Addr}, ops::BitXor, str::FromStr
 };
 
-use axum::{extract::Query, routing::get, Router};
-use serde::Deserialize;
+use axum::{
+    body::{Body, Bytes},
+    extract::{rejection::QueryRejection, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+struct ParamError {
+    param: &'static str,
+    source: Box<dyn Error>,
+}
+impl Display for ParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid `{}`: {}", self.param, self.source)
+    }
+}
+impl Error for ParamError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+fn parse<T>(param: &'static str, value: &str) -> Result<T, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: Error + 'static,
+{
+    T::from_str(value).map_err(|e| {
+        ParamError {
+            param,
+            source: e.into(),
+        }
+        .into()
+    })
+}
+
+// RFC 7807 problem details
+#[derive(Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    param: Option<&'static str>,
+}
+impl Problem {
+    fn new(status: StatusCode, detail: String, param: Option<&'static str>) -> Self {
+        Self {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            detail,
+            param,
+        }
+    }
+
+    fn bad_request(detail: String, param: Option<&'static str>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, detail, param)
+    }
+}
+impl From<Box<dyn Error>> for Problem {
+    fn from(e: Box<dyn Error>) -> Self {
+        match e.downcast_ref::<ParamError>() {
+            Some(p) => Self::bad_request(p.source.to_string(), Some(p.param)),
+            None => Self::bad_request(e.to_string(), None),
+        }
+    }
+}
+impl From<QueryRejection> for Problem {
+    fn from(e: QueryRejection) -> Self {
+        Self::bad_request(e.body_text(), None)
+    }
+}
+impl IntoResponse for Problem {
+    fn into_response(self) -> axum::response::Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::BAD_REQUEST);
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            serde_json::to_string(&self).unwrap(),
+        )
+            .into_response()
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    Add,
+    Sub,
+    Xor,
+    Rotl,
+}
+impl Mode {
+    fn apply(self, f: u8, k: u8) -> u8 {
+        match self {
+            Self::Add => f.wrapping_add(k),
+            Self::Sub => f.wrapping_sub(k),
+            Self::Xor => f.bitxor(k),
+            // rotation amount is taken modulo the octet width
+            Self::Rotl => f.rotate_left(k.into()),
+        }
+    }
+
+    // find a key octet that maps `f` onto `t`
+    fn invert(self, f: u8, t: u8) -> Option<u8> {
+        match self {
+            Self::Add => Some(t.wrapping_sub(f)),
+            Self::Sub => Some(f.wrapping_sub(t)),
+            Self::Xor => Some(f.bitxor(t)),
+            Self::Rotl => (0..8).find(|&r| f.rotate_left(r) == t).map(|r| r as u8),
+        }
+    }
+
+    fn dest<const N: usize>(self, from: [u8; N], key: [u8; N]) -> [u8; N] {
+        std::array::from_fn(|i| self.apply(from[i], key[i]))
+    }
+
+    fn key<const N: usize>(self, from: [u8; N], to: [u8; N]) -> Result<[u8; N], Box<dyn Error>> {
+        let mut key = [0; N];
+        for i in 0..N {
+            key[i] = self.invert(from[i], to[i]).ok_or(ParamError {
+                param: "to",
+                source: "no key maps `from` onto `to` in this mode".into(),
+            })?;
+        }
+        Ok(key)
+    }
+}
 
 #[derive(Deserialize)]
 struct Ipv4P1 {
     from: String,
     key: String,
+    mode: Option<Mode>,
 }
 impl Ipv4P1 {
-    fn cal(self) ->Result<String,Box<dyn Error>> {
-        let f = Ipv4Addr::from_str(&self.from)?;
-        let k = Ipv4Addr::from_str(&self.key)?;
-        let v = f
-            .octets()
-            .into_iter()
-            .zip(k.octets().into_iter())
-            .map(|(l, r)| l.overflowing_add(r).0)
-            .collect::<Vec<_>>();
-
-        let addr = unsafe {
-            let p = v.as_ptr() as *const [u8; 4];
-            Ipv4Addr::from(*p).to_string()
-        };
-        Ok(addr)
+    fn cal(self) -> Result<String, Box<dyn Error>> {
+        let f = parse::<Ipv4Addr>("from", &self.from)?;
+        let k = parse::<Ipv4Addr>("key", &self.key)?;
+        let mode = self.mode.unwrap_or(Mode::Add);
+        Ok(Ipv4Addr::from(mode.dest(f.octets(), k.octets())).to_string())
     }
 }
 
@@ -33,23 +158,14 @@ impl Ipv4P1 {
 struct Ipv4P2 {
     from: String,
     to: String,
+    mode: Option<Mode>,
 }
 impl Ipv4P2 {
-    fn cal(self) ->Result<String,Box<dyn Error>> {
-        let t = Ipv4Addr::from_str(&self.to)?;
-        let f = Ipv4Addr::from_str(&self.from)?;
-        let v = t
-            .octets()
-            .into_iter()
-            .zip(f.octets().into_iter())
-            .map(|(l, r)| l.overflowing_sub(r).0)
-            .collect::<Vec<_>>();
-
-        let addr = unsafe {
-            let p = v.as_ptr() as *const [u8; 4];
-            Ipv4Addr::from(*p).to_string()
-        };
-        Ok(addr)
+    fn cal(self) -> Result<String, Box<dyn Error>> {
+        let t = parse::<Ipv4Addr>("to", &self.to)?;
+        let f = parse::<Ipv4Addr>("from", &self.from)?;
+        let mode = self.mode.unwrap_or(Mode::Add);
+        Ok(Ipv4Addr::from(mode.key(f.octets(), t.octets())?).to_string())
     }
 }
 
@@ -57,61 +173,469 @@ impl Ipv4P2 {
 struct Ipv6P1 {
     from: String,
     key: String,
+    mode: Option<Mode>,
 }
 impl Ipv6P1 {
-    fn cal(self) ->Result<String,Box<dyn Error>> {
-        let f = Ipv6Addr::from_str(&self.from)?;
-
-        let k = Ipv6Addr::from_str(&self.key)?;
-        let v: Vec<_> = f
-            .octets()
-            .into_iter()
-            .zip(k.octets().into_iter())
-            .map(|(l, r)| l.bitxor(r))
-            .collect();
-
-        let addr = unsafe {
-            let p = v.as_ptr() as *const [u8; 16];
-            Ipv6Addr::from(*p).to_string()
-        };
-        Ok(addr)
+    fn cal(self) -> Result<String, Box<dyn Error>> {
+        let f = parse::<Ipv6Addr>("from", &self.from)?;
+        let k = parse::<Ipv6Addr>("key", &self.key)?;
+        let mode = self.mode.unwrap_or(Mode::Xor);
+        Ok(Ipv6Addr::from(mode.dest(f.octets(), k.octets())).to_string())
     }
 }
 #[derive(Deserialize)]
 struct Ipv6P2 {
     from: String,
     to: String,
+    mode: Option<Mode>,
 }
 impl Ipv6P2 {
-    fn cal(self) ->Result<String,Box<dyn Error>> {
-        let t = Ipv6Addr::from_str(&self.to)?;
-        let f = Ipv6Addr::from_str(&self.from)?;
-        let v = t
-            .octets()
-            .into_iter()
-            .zip(f.octets().into_iter())
-            .map(|(l, r)| l.bitxor(r))
-            .collect::<Vec<_>>();
-
-        let addr = unsafe {
-            let p = v.as_ptr() as *const [u8; 16];
-            Ipv6Addr::from(*p).to_string()
+    fn cal(self) -> Result<String, Box<dyn Error>> {
+        let t = parse::<Ipv6Addr>("to", &self.to)?;
+        let f = parse::<Ipv6Addr>("from", &self.from)?;
+        let mode = self.mode.unwrap_or(Mode::Xor);
+        Ok(Ipv6Addr::from(mode.key(f.octets(), t.octets())?).to_string())
+    }
+}
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Repr {
+    #[default]
+    Canonical,
+    Mapped,
+    Expanded,
+}
+impl Repr {
+    fn render(self, addr: IpAddr) -> String {
+        let v6 = match addr {
+            IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+            IpAddr::V6(v6) => v6,
+        };
+        match self {
+            Self::Canonical => addr.to_string(),
+            Self::Mapped => v6.to_string(),
+            Self::Expanded => v6.segments().map(|s| format!("{s:04x}")).join(":"),
+        }
+    }
+}
+
+// parse both addresses, folding IPv4-mapped IPv6 down to IPv4
+fn parse_pair(
+    (lp, l): (&'static str, &str),
+    (rp, r): (&'static str, &str),
+) -> Result<(IpAddr, IpAddr), Box<dyn Error>> {
+    let l = parse::<IpAddr>(lp, l)?.to_canonical();
+    let r = parse::<IpAddr>(rp, r)?.to_canonical();
+    if l.is_ipv4() != r.is_ipv4() {
+        return Err(ParamError {
+            param: rp,
+            source: format!("address family does not match `{lp}`").into(),
+        }
+        .into());
+    }
+    Ok((l, r))
+}
+
+#[derive(Deserialize)]
+struct AnyP1 {
+    from: String,
+    key: String,
+    mode: Option<Mode>,
+    #[serde(default)]
+    repr: Repr,
+}
+impl AnyP1 {
+    fn cal(self) -> Result<String, Box<dyn Error>> {
+        let addr = match parse_pair(("from", &self.from), ("key", &self.key))? {
+            (IpAddr::V4(f), IpAddr::V4(k)) => {
+                let mode = self.mode.unwrap_or(Mode::Add);
+                IpAddr::from(mode.dest(f.octets(), k.octets()))
+            }
+            (IpAddr::V6(f), IpAddr::V6(k)) => {
+                let mode = self.mode.unwrap_or(Mode::Xor);
+                IpAddr::from(mode.dest(f.octets(), k.octets()))
+            }
+            _ => unreachable!(),
+        };
+        Ok(self.repr.render(addr))
+    }
+}
+
+#[derive(Deserialize)]
+struct AnyP2 {
+    from: String,
+    to: String,
+    mode: Option<Mode>,
+    #[serde(default)]
+    repr: Repr,
+}
+impl AnyP2 {
+    fn cal(self) -> Result<String, Box<dyn Error>> {
+        let addr = match parse_pair(("from", &self.from), ("to", &self.to))? {
+            (IpAddr::V4(f), IpAddr::V4(t)) => {
+                let mode = self.mode.unwrap_or(Mode::Add);
+                IpAddr::from(mode.key(f.octets(), t.octets())?)
+            }
+            (IpAddr::V6(f), IpAddr::V6(t)) => {
+                let mode = self.mode.unwrap_or(Mode::Xor);
+                IpAddr::from(mode.key(f.octets(), t.octets())?)
+            }
+            _ => unreachable!(),
+        };
+        Ok(self.repr.render(addr))
+    }
+}
+
+#[derive(Deserialize)]
+struct Verify {
+    from: String,
+    key: String,
+    to: String,
+    mode: Option<Mode>,
+}
+
+#[derive(Serialize)]
+struct SegmentDiff {
+    index: usize,
+    expected: u16,
+    actual: u16,
+}
+
+#[derive(Serialize)]
+struct Verification {
+    valid: bool,
+    dest: String,
+    derived_key: Option<String>,
+    // why no key maps `from` onto `to`, when `derived_key` is absent
+    key_error: Option<String>,
+    diff: Vec<SegmentDiff>,
+}
+
+fn segment_diff<const N: usize>(expected: [u16; N], actual: [u16; N]) -> Vec<SegmentDiff> {
+    expected
+        .into_iter()
+        .zip(actual)
+        .enumerate()
+        .filter(|(_, (e, a))| e != a)
+        .map(|(index, (expected, actual))| SegmentDiff {
+            index,
+            expected,
+            actual,
+        })
+        .collect()
+}
+
+impl Verify {
+    // same family handling and key derivation as the /2/any routes
+    fn cal(self) -> Result<Verification, Box<dyn Error>> {
+        let (f, k) = parse_pair(("from", &self.from), ("key", &self.key))?;
+        let (_, t) = parse_pair(("from", &self.from), ("to", &self.to))?;
+        let (dest, derived_key, diff) = match (f, k, t) {
+            (IpAddr::V4(f), IpAddr::V4(k), IpAddr::V4(t)) => {
+                let mode = self.mode.unwrap_or(Mode::Add);
+                let d = mode.dest(f.octets(), k.octets());
+                let derived_key = mode.key(f.octets(), t.octets()).map(Ipv4Addr::from);
+                let diff = segment_diff(t.octets().map(u16::from), d.map(u16::from));
+                (IpAddr::from(d), derived_key.map(IpAddr::from), diff)
+            }
+            (IpAddr::V6(f), IpAddr::V6(k), IpAddr::V6(t)) => {
+                let mode = self.mode.unwrap_or(Mode::Xor);
+                let d = Ipv6Addr::from(mode.dest(f.octets(), k.octets()));
+                let derived_key = mode.key(f.octets(), t.octets()).map(Ipv6Addr::from);
+                let diff = segment_diff(t.segments(), d.segments());
+                (IpAddr::from(d), derived_key.map(IpAddr::from), diff)
+            }
+            _ => unreachable!(),
+        };
+        let (derived_key, key_error) = match derived_key {
+            Ok(key) => (Some(key.to_string()), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        Ok(Verification {
+            valid: diff.is_empty(),
+            dest: dest.to_string(),
+            derived_key,
+            key_error,
+            diff,
+        })
+    }
+}
+
+fn split_cidr(net: &str) -> Result<(&str, u32), Box<dyn Error>> {
+    let (addr, prefix) = net.split_once('/').ok_or(ParamError {
+        param: "net",
+        source: "missing prefix length".into(),
+    })?;
+    Ok((addr, parse("net", prefix)?))
+}
+
+#[derive(Deserialize)]
+struct CidrQuery {
+    net: String,
+    addr: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Ipv4Cidr {
+    network: Ipv4Addr,
+    broadcast: Ipv4Addr,
+    prefix: u32,
+    hosts: u64,
+    first_host: Ipv4Addr,
+    last_host: Ipv4Addr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contains: Option<bool>,
+}
+impl CidrQuery {
+    fn cal_v4(self) -> Result<Ipv4Cidr, Box<dyn Error>> {
+        let (addr, prefix) = split_cidr(&self.net)?;
+        if prefix > 32 {
+            return Err(ParamError {
+                param: "net",
+                source: "prefix length out of range".into(),
+            }
+            .into());
+        }
+        let addr = u32::from(parse::<Ipv4Addr>("net", addr)?);
+        let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+        let network = addr & mask;
+        let broadcast = network | !mask;
+
+        // /31 and /32 have no network or broadcast address to reserve (RFC 3021)
+        let (hosts, first_host, last_host) = if prefix >= 31 {
+            (1 << (32 - prefix), network, broadcast)
+        } else {
+            ((1 << (32 - prefix)) - 2, network + 1, broadcast - 1)
+        };
+
+        let contains = match self.addr {
+            Some(a) => Some(u32::from(parse::<Ipv4Addr>("addr", &a)?) & mask == network),
+            None => None,
+        };
+
+        Ok(Ipv4Cidr {
+            network: network.into(),
+            broadcast: broadcast.into(),
+            prefix,
+            hosts,
+            first_host: first_host.into(),
+            last_host: last_host.into(),
+            contains,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Ipv6Cidr {
+    network: Ipv6Addr,
+    last: Ipv6Addr,
+    prefix: u32,
+    // a /0 holds 2^128 addresses, which does not fit in a u128
+    hosts: Option<u128>,
+    first_host: Ipv6Addr,
+    last_host: Ipv6Addr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contains: Option<bool>,
+}
+impl CidrQuery {
+    fn cal_v6(self) -> Result<Ipv6Cidr, Box<dyn Error>> {
+        let (addr, prefix) = split_cidr(&self.net)?;
+        if prefix > 128 {
+            return Err(ParamError {
+                param: "net",
+                source: "prefix length out of range".into(),
+            }
+            .into());
+        }
+        let addr = u128::from(parse::<Ipv6Addr>("net", addr)?);
+        let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+        let network = addr & mask;
+        let last = network | !mask;
+
+        let contains = match self.addr {
+            Some(a) => Some(u128::from(parse::<Ipv6Addr>("addr", &a)?) & mask == network),
+            None => None,
         };
-        Ok(addr)
+
+        // IPv6 has no broadcast address, every address in the prefix is usable
+        Ok(Ipv6Cidr {
+            network: network.into(),
+            last: last.into(),
+            prefix,
+            hosts: 1u128.checked_shl(128 - prefix),
+            first_host: network.into(),
+            last_host: last.into(),
+            contains,
+        })
+    }
+}
+
+async fn v4p1(payload: Result<Query<Ipv4P1>, QueryRejection>) -> Result<String, Problem> {
+    let Query(payload) = payload?;
+    Ok(payload.cal()?)
+}
+
+async fn v4p2(payload: Result<Query<Ipv4P2>, QueryRejection>) -> Result<String, Problem> {
+    let Query(payload) = payload?;
+    Ok(payload.cal()?)
+}
+async fn v6p1(payload: Result<Query<Ipv6P1>, QueryRejection>) -> Result<String, Problem> {
+    let Query(payload) = payload?;
+    Ok(payload.cal()?)
+}
+async fn v6p2(payload: Result<Query<Ipv6P2>, QueryRejection>) -> Result<String, Problem> {
+    let Query(payload) = payload?;
+    Ok(payload.cal()?)
+}
+async fn anyp1(payload: Result<Query<AnyP1>, QueryRejection>) -> Result<String, Problem> {
+    let Query(payload) = payload?;
+    Ok(payload.cal()?)
+}
+async fn anyp2(payload: Result<Query<AnyP2>, QueryRejection>) -> Result<String, Problem> {
+    let Query(payload) = payload?;
+    Ok(payload.cal()?)
+}
+async fn verify(
+    payload: Result<Query<Verify>, QueryRejection>,
+) -> Result<Json<Verification>, Problem> {
+    let Query(payload) = payload?;
+    Ok(Json(payload.cal()?))
+}
+async fn v4cidr(
+    payload: Result<Query<CidrQuery>, QueryRejection>,
+) -> Result<Json<Ipv4Cidr>, Problem> {
+    let Query(payload) = payload?;
+    Ok(Json(payload.cal_v4()?))
+}
+async fn v6cidr(
+    payload: Result<Query<CidrQuery>, QueryRejection>,
+) -> Result<Json<Ipv6Cidr>, Problem> {
+    let Query(payload) = payload?;
+    Ok(Json(payload.cal_v6()?))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BatchItem {
+    Dest {
+        from: String,
+        key: String,
+        mode: Option<Mode>,
+    },
+    Key {
+        from: String,
+        to: String,
+        mode: Option<Mode>,
+    },
+}
+impl BatchItem {
+    fn cal(self) -> Result<String, Box<dyn Error>> {
+        match self {
+            Self::Dest { from, key, mode } if from.contains(':') => {
+                Ipv6P1 { from, key, mode }.cal()
+            }
+            Self::Dest { from, key, mode } => Ipv4P1 { from, key, mode }.cal(),
+            Self::Key { from, to, mode } if from.contains(':') => Ipv6P2 { from, to, mode }.cal(),
+            Self::Key { from, to, mode } => Ipv4P2 { from, to, mode }.cal(),
+        }
     }
 }
-async fn v4p1(Query(payload): Query<Ipv4P1>) -> String {
-    payload.cal().unwrap()
+
+#[derive(Serialize)]
+struct BatchResult {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+impl BatchResult {
+    fn new(index: usize, item: Result<BatchItem, serde_json::Error>) -> Self {
+        let (result, error) = match item.map_err(Into::into).and_then(BatchItem::cal) {
+            Ok(r) => (Some(r), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        Self {
+            index,
+            result,
+            error,
+        }
+    }
 }
 
-async fn v4p2(Query(payload): Query<Ipv4P2>) -> String {
-    payload.cal().unwrap()
+enum BatchFormat {
+    Json,
+    NdJson,
 }
-async fn v6p1(Query(payload): Query<Ipv6P1>) -> String {
-    payload.cal().unwrap()
+
+fn check_batch_content_type(headers: &HeaderMap) -> Option<BatchFormat> {
+    let mime = headers
+        .get(header::CONTENT_TYPE)?
+        .to_str()
+        .ok()?
+        .parse::<mime::Mime>()
+        .ok()?;
+    if mime.type_() != "application" {
+        None
+    } else if mime.subtype() == "json" || mime.suffix().is_some_and(|name| name == "json") {
+        Some(BatchFormat::Json)
+    } else if matches!(mime.subtype().as_str(), "x-ndjson" | "ndjson") {
+        Some(BatchFormat::NdJson)
+    } else {
+        None
+    }
 }
-async fn v6p2(Query(payload): Query<Ipv6P2>) -> String {
-    payload.cal().unwrap()
+
+async fn batch(headers: HeaderMap, bytes: Bytes) -> Result<Response, Problem> {
+    let format = check_batch_content_type(&headers).ok_or(Problem::new(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        "expected application/json or application/x-ndjson".to_string(),
+        None,
+    ))?;
+
+    match format {
+        BatchFormat::Json => {
+            let items: Vec<serde_json::Value> = serde_json::from_slice(&bytes)
+                .map_err(|e| Problem::bad_request(e.to_string(), None))?;
+            // emit the array piecewise so results go out as they are computed
+            let results = items.into_iter().enumerate().map(|(i, v)| {
+                let result = BatchResult::new(i, serde_json::from_value(v));
+                let s = serde_json::to_string(&result).unwrap();
+                Ok::<_, Infallible>(if i == 0 { s } else { format!(",{s}") })
+            });
+            let body = std::iter::once(Ok("[".to_string()))
+                .chain(results)
+                .chain(std::iter::once(Ok("]".to_string())));
+            Ok((
+                [(header::CONTENT_TYPE, "application/json")],
+                Body::from_stream(stream::iter(body)),
+            )
+                .into_response())
+        }
+        BatchFormat::NdJson => {
+            let text = String::from_utf8(bytes.to_vec())
+                .map_err(|e| Problem::bad_request(e.to_string(), None))?;
+            // enumerate before skipping blank lines, so `index` is the input line
+            let lines: Vec<(usize, String)> = text
+                .lines()
+                .enumerate()
+                .filter(|(_, l)| !l.trim().is_empty())
+                .map(|(i, l)| (i, l.to_string()))
+                .collect();
+            let results = lines.into_iter().map(|(i, line)| {
+                let result = BatchResult::new(i, serde_json::from_str(&line));
+                let mut s = serde_json::to_string(&result).unwrap();
+                s.push('\n');
+                Ok::<_, Infallible>(s)
+            });
+            Ok((
+                [(header::CONTENT_TYPE, "application/x-ndjson")],
+                Body::from_stream(stream::iter(results)),
+            )
+                .into_response())
+        }
+    }
 }
 
 pub fn router() -> Router {
@@ -120,4 +644,10 @@ pub fn router() -> Router {
         .route("/2/key", get(v4p2))
         .route("/2/v6/dest", get(v6p1))
         .route("/2/v6/key", get(v6p2))
+        .route("/2/any/dest", get(anyp1))
+        .route("/2/any/key", get(anyp2))
+        .route("/2/verify", get(verify))
+        .route("/2/cidr", get(v4cidr))
+        .route("/2/v6/cidr", get(v6cidr))
+        .route("/2/batch", post(batch))
 }
@@ -1,112 +1,296 @@
+use std::{fmt::Display, sync::Arc};
 
 use axum::{
     async_trait,
     body::Bytes,
-    extract::{FromRequest, Request},
+    extract::{
+        multipart::MultipartError,
+        rejection::{BytesRejection, QueryRejection},
+        FromRequest, Multipart, Query, Request, State,
+    },
     http::{header, HeaderMap, StatusCode},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::post,
-    Router,
+    Json, Router,
 };
-use cargo_manifest::Manifest;
-use serde::Deserialize;
+use cargo_manifest::{Dependency, Manifest};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use shuttle_runtime::SecretStore;
 
 #[derive(Debug)]
 struct Metadata {
     orders: Vec<Orders>,
+    rejected: Vec<Rejected>,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 struct Orders {
     item: String,
     quantity: u32,
 }
+#[derive(Debug, Serialize)]
+struct Rejected {
+    index: usize,
+    error: String,
+}
 
 #[derive(Debug)]
 enum MyErr {
     MetadataError,
-    InvalidError,
+    InvalidError(String),
+    TypeError {
+        path: &'static str,
+        expected: &'static str,
+    },
     MagicError,
     ContentUnsupportedError,
-    UnknownError,    
+    NotAcceptableError,
+    PayloadTooLargeError,
+    BodyError(String),
+}
+impl MyErr {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::MetadataError => StatusCode::NO_CONTENT,
+            Self::InvalidError(_)
+            | Self::TypeError { .. }
+            | Self::MagicError
+            | Self::BodyError(_) => StatusCode::BAD_REQUEST,
+            Self::ContentUnsupportedError => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::NotAcceptableError => StatusCode::NOT_ACCEPTABLE,
+            Self::PayloadTooLargeError => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::MetadataError => "no_metadata",
+            Self::InvalidError(_) => "invalid_manifest",
+            Self::TypeError { .. } => "type_mismatch",
+            Self::MagicError => "magic_keyword_missing",
+            Self::ContentUnsupportedError => "unsupported_content_type",
+            Self::NotAcceptableError => "not_acceptable",
+            Self::PayloadTooLargeError => "payload_too_large",
+            Self::BodyError(_) => "invalid_body",
+        }
+    }
 }
+impl Display for MyErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MetadataError => f.write_str("No orders in manifest metadata"),
+            Self::InvalidError(e) => write!(f, "Invalid manifest: {e}"),
+            Self::TypeError { path, expected } => write!(f, "`{path}` must be {expected}"),
+            Self::MagicError => f.write_str("Magic keyword not provided"),
+            Self::ContentUnsupportedError => f.write_str("Unsupported content type"),
+            Self::NotAcceptableError => f.write_str("Not acceptable"),
+            Self::PayloadTooLargeError => f.write_str("Payload too large"),
+            Self::BodyError(e) => write!(f, "Failed to read body: {e}"),
+        }
+    }
+}
+impl From<BytesRejection> for MyErr {
+    fn from(e: BytesRejection) -> Self {
+        if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            Self::PayloadTooLargeError
+        } else {
+            Self::BodyError(e.body_text())
+        }
+    }
+}
+impl From<QueryRejection> for MyErr {
+    fn from(e: QueryRejection) -> Self {
+        Self::BodyError(e.body_text())
+    }
+}
+impl From<MultipartError> for MyErr {
+    fn from(e: MultipartError) -> Self {
+        if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            Self::PayloadTooLargeError
+        } else {
+            Self::BodyError(e.body_text())
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
 impl IntoResponse for MyErr {
     fn into_response(self) -> axum::response::Response {
-        match self {
-            Self::InvalidError => {
-                (StatusCode::BAD_REQUEST, "Invalid manifest".to_string()).into_response()
-            }
-            Self::MetadataError => StatusCode::NO_CONTENT.into_response(),
-            Self::MagicError => {
-                (StatusCode::BAD_REQUEST, "Magic keyword not provided").into_response()
-            }
-            Self::ContentUnsupportedError => StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response(),
-            Self::UnknownError => panic!(),
+        let status = self.status();
+        // 204 must not carry a body
+        if status == StatusCode::NO_CONTENT {
+            return status.into_response();
         }
+        let body = ErrorBody {
+            error: self.code(),
+            message: self.to_string(),
+        };
+        (status, Json(body)).into_response()
     }
 }
 
+#[derive(Clone, Copy)]
+enum KeywordMatch {
+    Any,
+    All,
+}
+
+pub struct KeywordPolicy {
+    keywords: Vec<String>,
+    matching: KeywordMatch,
+    case_sensitive: bool,
+    // stands in for `[workspace.package]` when a manifest sets `keywords.workspace = true`
+    workspace_keywords: Option<Vec<String>>,
+}
+impl KeywordPolicy {
+    pub fn from_secrets(secrets: &SecretStore) -> Self {
+        let list = |key: &str| {
+            secrets.get(key).map(|v| {
+                v.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+            })
+        };
+        let matching = match secrets.get("MANIFEST_KEYWORDS_MATCH").as_deref() {
+            Some("all") => KeywordMatch::All,
+            _ => KeywordMatch::Any,
+        };
+        let case_sensitive = secrets
+            .get("MANIFEST_KEYWORDS_CASE_SENSITIVE")
+            .is_none_or(|v| v != "false");
+
+        Self {
+            keywords: list("MANIFEST_KEYWORDS")
+                .unwrap_or_else(|| vec!["Christmas 2024".to_string()]),
+            matching,
+            case_sensitive,
+            workspace_keywords: list("MANIFEST_WORKSPACE_KEYWORDS"),
+        }
+    }
+
+    fn check(&self, keywords: &[String]) -> bool {
+        let has = |required: &String| {
+            keywords.iter().any(|k| {
+                if self.case_sensitive {
+                    k == required
+                } else {
+                    k.to_lowercase() == required.to_lowercase()
+                }
+            })
+        };
+        match self.matching {
+            KeywordMatch::Any => self.keywords.is_empty() || self.keywords.iter().any(has),
+            KeywordMatch::All => self.keywords.iter().all(has),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MyState {
+    policy: Arc<KeywordPolicy>,
+}
+
+#[derive(Clone, Copy)]
 enum ContentType {
     Toml,
     Yaml,
     Json
 }
 
-#[async_trait]
-impl<S> FromRequest<S> for Metadata
-where
-    S: Send + Sync,
-{
-    type Rejection = MyErr;
-    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
-        if let Some(content_type) = check_content_type(req.headers()) {
-            let bytes = Bytes::from_request(req, state)
-                .await
-                .map_err(|_| MyErr::UnknownError)?;
-
-            let manifest = match content_type {
-                ContentType::Toml => Manifest::from_slice(&bytes).map_err(|_| MyErr::InvalidError)?,
-                ContentType::Yaml => serde_yaml::from_slice(&bytes).map_err(|_| MyErr::InvalidError)?,
-                ContentType::Json => serde_json::from_slice(&bytes).map_err(|_|MyErr::InvalidError)?,
+fn invalid(e: impl Display) -> MyErr {
+    MyErr::InvalidError(e.to_string())
+}
+
+fn parse_document<T: DeserializeOwned>(
+    content_type: ContentType,
+    bytes: &[u8],
+) -> Result<T, MyErr> {
+    let document = match content_type {
+        ContentType::Toml => {
+            let s = std::str::from_utf8(bytes).map_err(invalid)?;
+            toml::from_str(s).map_err(invalid)?
+        }
+        ContentType::Yaml => serde_yaml::from_slice(bytes).map_err(invalid)?,
+        ContentType::Json => serde_json::from_slice(bytes).map_err(invalid)?,
+    };
+    Ok(document)
+}
+
+fn parse_manifest(content_type: ContentType, bytes: &[u8]) -> Result<Manifest, MyErr> {
+    match content_type {
+        ContentType::Toml => Manifest::from_slice(bytes).map_err(invalid),
+        _ => parse_document(content_type, bytes),
+    }
+}
+
+fn extract_metadata(
+    manifest: Manifest,
+    policy: &KeywordPolicy,
+    workspace_keywords: Option<&[String]>,
+) -> Result<Metadata, MyErr> {
+    let package = manifest.package;
+    if let Some(package) = &package {
+        if let Some(keywords) = &package.keywords {
+            let keywords = match keywords.clone().as_local() {
+                Some(keywords) => keywords,
+                None => workspace_keywords.ok_or(MyErr::MagicError)?.to_vec(),
             };
-                
-
-            let package = manifest.package;
-            if let Some(package) = &package {
-                if let Some(keywords) = &package.keywords {
-                    if let Some(keywords) = keywords.clone().as_local() {
-                        if !keywords.contains(&"Christmas 2024".to_string()) {
-                            return Err(MyErr::MagicError);
-                        }
-                    }
-                }else{
-                    return Err(MyErr::MagicError);
-                }
+            if !policy.check(&keywords) {
+                return Err(MyErr::MagicError);
             }
+        } else {
+            return Err(MyErr::MagicError);
+        }
+    }
 
-            let metadata = package.map(|p| p.metadata).flatten();
-
-            let metadata = if let Some(v) = metadata {
-                let mut orders = Vec::new();
-                if let Some(m) = v.as_table() {
-                    let v = m.get("orders");
-                    if let Some(a) = v {
-                        for v in a.as_array().unwrap() {
-                            let m: Result<Orders, toml::de::Error> = v.clone().try_into();
-                            if let Ok(m) = m {
-                                orders.push(m);
-                            }
-                        }
-                    }
-                }
-                if orders.is_empty() {
-                    return Err(MyErr::MetadataError);
+    let metadata = package.and_then(|p| p.metadata);
+
+    let metadata = if let Some(v) = metadata {
+        let mut orders = Vec::new();
+        let mut rejected = Vec::new();
+        let m = v.as_table().ok_or(MyErr::TypeError {
+            path: "package.metadata",
+            expected: "a table",
+        })?;
+        if let Some(a) = m.get("orders") {
+            let a = a.as_array().ok_or(MyErr::TypeError {
+                path: "package.metadata.orders",
+                expected: "an array",
+            })?;
+            for (index, v) in a.iter().enumerate() {
+                let m: Result<Orders, toml::de::Error> = v.clone().try_into();
+                match m {
+                    Ok(m) => orders.push(m),
+                    Err(e) => rejected.push(Rejected {
+                        index,
+                        error: e.message().to_string(),
+                    }),
                 }
-                Metadata { orders }
-            } else {
-                return Err(MyErr::MetadataError);
-            };
+            }
+        }
+        Metadata { orders, rejected }
+    } else {
+        return Err(MyErr::MetadataError);
+    };
+
+    Ok(metadata)
+}
+
+#[async_trait]
+impl FromRequest<MyState> for Metadata {
+    type Rejection = MyErr;
+    async fn from_request(req: Request, state: &MyState) -> Result<Self, Self::Rejection> {
+        if let Some(content_type) = check_content_type(req.headers()) {
+            let bytes = Bytes::from_request(req, state).await?;
 
-            Ok(metadata)
+            let manifest = parse_manifest(content_type, &bytes)?;
+            let policy = &state.policy;
+            extract_metadata(manifest, policy, policy.workspace_keywords.as_deref())
         } else {
             Err(MyErr::ContentUnsupportedError)
         }
@@ -126,6 +310,10 @@ fn check_content_type(headers: &HeaderMap) -> Option<ContentType> {
         return None;
     };
 
+    content_type_from_str(content_type)
+}
+
+fn content_type_from_str(content_type: &str) -> Option<ContentType> {
     let mime = if let Ok(mime) = content_type.parse::<mime::Mime>() {
         mime
     } else {
@@ -143,22 +331,976 @@ fn check_content_type(headers: &HeaderMap) -> Option<ContentType> {
     None
 }
 
-async fn p1(m: Metadata) -> String {
+#[derive(Clone, Copy)]
+enum Format {
+    Text,
+    Json,
+    Yaml,
+    Toml,
+    Csv,
+}
+
+fn check_accept(headers: &HeaderMap) -> Option<Format> {
+    let accept = if let Some(accept) = headers.get(header::ACCEPT) {
+        accept
+    } else {
+        return Some(Format::Text);
+    };
+
+    let accept = accept.to_str().ok()?;
+
+    let mut best: Option<(f32, Format)> = None;
+    for mime in accept
+        .split(',')
+        .filter_map(|m| m.trim().parse::<mime::Mime>().ok())
+    {
+        let q = mime
+            .get_param("q")
+            .and_then(|q| q.as_str().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let format = match (mime.type_().as_str(), mime.subtype().as_str()) {
+            ("*", "*") | ("text", "*") | ("text", "plain") => Format::Text,
+            ("application", "json" | "*") => Format::Json,
+            ("application" | "text", "yaml" | "x-yaml") => Format::Yaml,
+            ("application", "toml") => Format::Toml,
+            ("text", "csv") => Format::Csv,
+            _ => continue,
+        };
+        if q > 0.0 && best.is_none_or(|(b, _)| q > b) {
+            best = Some((q, format));
+        }
+    }
+    best.map(|(_, format)| format)
+}
+
+fn render<T: Serialize>(format: Format, value: &T) -> Result<Response, MyErr> {
+    let (content_type, body) = match format {
+        Format::Json => (
+            "application/json",
+            serde_json::to_string(value).map_err(invalid),
+        ),
+        Format::Yaml => (
+            "application/yaml",
+            serde_yaml::to_string(value).map_err(invalid),
+        ),
+        Format::Toml => ("application/toml", toml::to_string(value).map_err(invalid)),
+        Format::Text | Format::Csv => return Err(MyErr::NotAcceptableError),
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], body?).into_response())
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[derive(Serialize)]
+struct OrderList<'a> {
+    orders: &'a [Orders],
+}
+
+#[derive(Deserialize)]
+struct ManifestQuery {
+    #[serde(default)]
+    summary: bool,
+}
+
+#[derive(Serialize)]
+struct ItemTotal {
+    item: String,
+    quantity: u64,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    items: Vec<ItemTotal>,
+    total_quantity: u64,
+    rejected: Vec<Rejected>,
+}
+impl From<Metadata> for Summary {
+    fn from(m: Metadata) -> Self {
+        let mut items: Vec<ItemTotal> = Vec::new();
+        for o in m.orders {
+            match items.iter_mut().find(|t| t.item == o.item) {
+                Some(t) => t.quantity += u64::from(o.quantity),
+                None => items.push(ItemTotal {
+                    item: o.item,
+                    quantity: o.quantity.into(),
+                }),
+            }
+        }
+        let total_quantity = items.iter().map(|t| t.quantity).sum();
+        Self {
+            items,
+            total_quantity,
+            rejected: m.rejected,
+        }
+    }
+}
+
+async fn p1(
+    q: Result<Query<ManifestQuery>, QueryRejection>,
+    headers: HeaderMap,
+    m: Metadata,
+) -> Result<Response, MyErr> {
+    let Query(q) = q?;
+    let format = check_accept(&headers).ok_or(MyErr::NotAcceptableError)?;
+
+    if q.summary {
+        let format = match format {
+            Format::Text => Format::Json,
+            f => f,
+        };
+        return render(format, &Summary::from(m));
+    }
+    if m.orders.is_empty() {
+        return Err(MyErr::MetadataError);
+    }
+
     let mut ret = String::new();
-    for s in m
-        .orders
+    match format {
+        Format::Text => {
+            for s in m
+                .orders
+                .into_iter()
+                .map(|o| format!("{}: {}", o.item, o.quantity))
+            {
+                ret.push_str(&s);
+                ret.push('\n');
+            }
+            if !ret.is_empty() {
+                ret.pop();
+            }
+        }
+        Format::Csv => {
+            ret.push_str("item,quantity\n");
+            for o in m.orders {
+                ret.push_str(&format!("{},{}\n", csv_field(&o.item), o.quantity));
+            }
+            return Ok(([(header::CONTENT_TYPE, "text/csv")], ret).into_response());
+        }
+        format => return render(format, &OrderList { orders: &m.orders }),
+    }
+    Ok(ret.into_response())
+}
+
+#[derive(Serialize)]
+struct MemberReport {
+    name: String,
+    orders: Vec<Orders>,
+    rejected: Vec<Rejected>,
+    // the member manifest after workspace inheritance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest: Option<toml::Table>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct WorkspaceReport {
+    members: Vec<MemberReport>,
+}
+
+// package keys a member can take from `[workspace.package]`
+const INHERITABLE_KEYS: &[&str] = &[
+    "version",
+    "authors",
+    "description",
+    "documentation",
+    "readme",
+    "homepage",
+    "repository",
+    "license",
+    "license-file",
+    "keywords",
+    "categories",
+    "publish",
+    "edition",
+    "rust-version",
+    "exclude",
+    "include",
+];
+
+const DEPENDENCY_KEYS: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+fn is_inherited(value: &toml::Value) -> bool {
+    value.get("workspace") == Some(&toml::Value::Boolean(true))
+}
+
+// `dep = { workspace = true }` takes the entry from `[workspace.dependencies]`,
+// with its own `features` added and its other keys (like `optional`) on top
+fn inherit_dependencies(
+    section: &str,
+    deps: &mut toml::Table,
+    workspace: Option<&toml::Table>,
+) -> Result<(), MyErr> {
+    for (name, dep) in deps.iter_mut() {
+        if !is_inherited(dep) {
+            continue;
+        }
+        let base = workspace.and_then(|w| w.get(name)).ok_or_else(|| {
+            invalid(format!(
+                "`{section}.{name}` is inherited but `workspace.dependencies.{name}` is not set"
+            ))
+        })?;
+        let mut resolved = match base {
+            toml::Value::String(version) => {
+                toml::Table::from_iter([("version".to_string(), version.clone().into())])
+            }
+            toml::Value::Table(t) => t.clone(),
+            _ => {
+                return Err(invalid(format!(
+                    "`workspace.dependencies.{name}` must be a string or a table"
+                )))
+            }
+        };
+        for (key, value) in dep.as_table().into_iter().flatten() {
+            match (key.as_str(), resolved.get_mut(key)) {
+                ("workspace", _) => {}
+                ("features", Some(toml::Value::Array(features))) => {
+                    features.extend(value.as_array().into_iter().flatten().cloned());
+                }
+                _ => {
+                    resolved.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        *dep = toml::Value::Table(resolved);
+    }
+    Ok(())
+}
+
+// Cargo's workspace inheritance, done on the raw document as a request has no
+// files on disk to resolve paths against
+fn inherit(member: &mut toml::Table, workspace: &toml::Table) -> Result<(), MyErr> {
+    let section = |key: &str| workspace.get(key).and_then(toml::Value::as_table);
+    let workspace_package = section("package");
+
+    if let Some(package) = member
+        .get_mut("package")
+        .and_then(toml::Value::as_table_mut)
+    {
+        for (key, value) in package.iter_mut() {
+            if !is_inherited(value) {
+                continue;
+            }
+            if !INHERITABLE_KEYS.contains(&key.as_str()) {
+                return Err(invalid(format!("`package.{key}` cannot be inherited")));
+            }
+            *value = workspace_package
+                .and_then(|p| p.get(key))
+                .cloned()
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "`package.{key}` is inherited but `workspace.package.{key}` is not set"
+                    ))
+                })?;
+        }
+    }
+
+    let workspace_deps = section("dependencies");
+    for key in DEPENDENCY_KEYS {
+        if let Some(deps) = member.get_mut(*key).and_then(toml::Value::as_table_mut) {
+            inherit_dependencies(key, deps, workspace_deps)?;
+        }
+    }
+    for (cfg, target) in member
+        .get_mut("target")
+        .and_then(toml::Value::as_table_mut)
         .into_iter()
-        .map(|o| format!("{}: {}", o.item, o.quantity))
+        .flatten()
     {
-        ret.push_str(&s);
-        ret.push('\n');
+        for key in DEPENDENCY_KEYS {
+            if let Some(deps) = target.get_mut(*key).and_then(toml::Value::as_table_mut) {
+                inherit_dependencies(&format!("target.{cfg}.{key}"), deps, workspace_deps)?;
+            }
+        }
     }
-    if !ret.is_empty() {
-        ret.pop();
+
+    if let Some(lints) = member.get_mut("lints") {
+        if is_inherited(lints) {
+            *lints = workspace
+                .get("lints")
+                .cloned()
+                .ok_or_else(|| invalid("`lints` is inherited but `workspace.lints` is not set"))?;
+        }
+    }
+    Ok(())
+}
+
+// `*` and `?` within a path segment, like Cargo's member globs
+fn glob_match(pattern: &str, path: &str) -> bool {
+    fn segment(p: &[u8], s: &[u8]) -> bool {
+        match (p.first(), s.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => segment(&p[1..], s) || (!s.is_empty() && segment(p, &s[1..])),
+            (Some(b'?'), Some(_)) => segment(&p[1..], &s[1..]),
+            (Some(a), Some(b)) => a == b && segment(&p[1..], &s[1..]),
+            _ => false,
+        }
+    }
+    let normalize = |p: &'_ str| p.trim_start_matches("./").trim_end_matches('/').to_string();
+    let (pattern, path) = (normalize(pattern), normalize(path));
+    let (pattern, path): (Vec<_>, Vec<_>) =
+        (pattern.split('/').collect(), path.split('/').collect());
+    pattern.len() == path.len()
+        && pattern
+            .iter()
+            .zip(&path)
+            .all(|(p, s)| segment(p.as_bytes(), s.as_bytes()))
+}
+
+fn is_member(workspace: &toml::Table, path: &str) -> bool {
+    let list = |key: &str| {
+        workspace
+            .get(key)
+            .and_then(toml::Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(toml::Value::as_str)
+    };
+    list("members").any(|m| glob_match(m, path)) && !list("exclude").any(|e| glob_match(e, path))
+}
+
+// The part named `workspace` is the root manifest. Every other part is a member
+// named by its path in the workspace, e.g. `crates/a`.
+async fn p2(
+    State(state): State<MyState>,
+    mut multipart: Multipart,
+) -> Result<Json<WorkspaceReport>, MyErr> {
+    let mut root = None;
+    let mut parts = Vec::new();
+    while let Some(field) = multipart.next_field().await? {
+        let content_type = field
+            .content_type()
+            .and_then(content_type_from_str)
+            .unwrap_or(ContentType::Toml);
+        let path = field
+            .name()
+            .or(field.file_name())
+            .unwrap_or_default()
+            .to_string();
+        let bytes = field.bytes().await?;
+        if path == "workspace" {
+            parse_manifest(content_type, &bytes)?;
+            root = Some(parse_document::<toml::Table>(content_type, &bytes)?);
+        } else {
+            parts.push((path, content_type, bytes));
+        }
+    }
+
+    let workspace = root
+        .and_then(|mut root| match root.remove("workspace") {
+            Some(toml::Value::Table(workspace)) => Some(workspace),
+            _ => None,
+        })
+        .ok_or(MyErr::InvalidError(
+            "missing `workspace` part with a [workspace] table".to_string(),
+        ))?;
+
+    let members = parts
+        .into_iter()
+        .map(|(path, content_type, bytes)| {
+            let mut raw: toml::Table = match parse_document(content_type, &bytes) {
+                Ok(raw) => raw,
+                Err(e) => return MemberReport::failed(path, e),
+            };
+            let name = raw
+                .get("package")
+                .and_then(|p| p.get("name"))
+                .and_then(toml::Value::as_str)
+                .map_or_else(|| path.clone(), str::to_string);
+            if !is_member(&workspace, &path) {
+                let e = invalid(format!("`{path}` is not in workspace.members"));
+                return MemberReport::failed(name, e);
+            }
+
+            let metadata = inherit(&mut raw, &workspace)
+                .and_then(|()| {
+                    toml::Value::Table(raw.clone())
+                        .try_into::<Manifest>()
+                        .map_err(invalid)
+                })
+                .and_then(|manifest| extract_metadata(manifest, &state.policy, None));
+            match metadata {
+                Ok(m) => MemberReport {
+                    name,
+                    orders: m.orders,
+                    rejected: m.rejected,
+                    manifest: Some(raw),
+                    error: None,
+                },
+                Err(e) => MemberReport::failed(name, e),
+            }
+        })
+        .collect();
+
+    Ok(Json(WorkspaceReport { members }))
+}
+impl MemberReport {
+    fn failed(name: String, e: MyErr) -> Self {
+        Self {
+            name,
+            orders: Vec::new(),
+            rejected: Vec::new(),
+            manifest: None,
+            error: Some(e.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+#[derive(Serialize)]
+struct Finding {
+    severity: Severity,
+    path: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct LintReport {
+    findings: Vec<Finding>,
+}
+
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "cargo-features",
+    "package",
+    "project",
+    "workspace",
+    "lib",
+    "bin",
+    "example",
+    "test",
+    "bench",
+    "dependencies",
+    "dev-dependencies",
+    "build-dependencies",
+    "target",
+    "features",
+    "patch",
+    "replace",
+    "profile",
+    "badges",
+    "lints",
+];
+
+const PACKAGE_KEYS: &[&str] = &[
+    "name",
+    "version",
+    "authors",
+    "edition",
+    "rust-version",
+    "description",
+    "documentation",
+    "readme",
+    "homepage",
+    "repository",
+    "license",
+    "license-file",
+    "keywords",
+    "categories",
+    "workspace",
+    "build",
+    "links",
+    "exclude",
+    "include",
+    "publish",
+    "metadata",
+    "default-run",
+    "autolib",
+    "autobins",
+    "autoexamples",
+    "autotests",
+    "autobenches",
+    "resolver",
+];
+
+const EDITIONS: &[&str] = &["2015", "2018", "2021", "2024"];
+
+// JSON pointer (RFC 6901) to a key in the manifest
+fn pointer(path: &[&str]) -> String {
+    path.iter()
+        .map(|p| format!("/{}", p.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+impl LintReport {
+    fn push(&mut self, severity: Severity, path: &[&str], message: String) {
+        self.findings.push(Finding {
+            severity,
+            path: pointer(path),
+            message,
+        });
+    }
+
+    // checks that need the document before `Manifest` drops unknown keys,
+    // an invalid edition is removed so the rest of the manifest still parses
+    fn lint_raw(&mut self, raw: &mut toml::Table) {
+        for key in raw.keys() {
+            if !TOP_LEVEL_KEYS.contains(&key.as_str()) {
+                self.push(Severity::Warning, &[key], format!("unknown key `{key}`"));
+            }
+        }
+
+        let package = match raw.get_mut("package").and_then(toml::Value::as_table_mut) {
+            Some(package) => package,
+            None => return,
+        };
+        for key in package.keys() {
+            if !PACKAGE_KEYS.contains(&key.as_str()) {
+                self.push(
+                    Severity::Warning,
+                    &["package", key],
+                    format!("unknown key `{key}`"),
+                );
+            }
+        }
+        let edition_ok = match package.get("edition") {
+            None => true,
+            Some(toml::Value::String(edition)) => EDITIONS.contains(&edition.as_str()),
+            Some(toml::Value::Table(t)) => t.get("workspace") == Some(&toml::Value::Boolean(true)),
+            Some(_) => false,
+        };
+        if !edition_ok {
+            let edition = package.remove("edition").unwrap();
+            self.push(
+                Severity::Error,
+                &["package", "edition"],
+                format!(
+                    "invalid edition {edition}, expected one of {}",
+                    EDITIONS.join(", ")
+                ),
+            );
+        }
+    }
+
+    fn lint_manifest(&mut self, manifest: &Manifest) {
+        if let Some(package) = &manifest.package {
+            if package.license.is_none() && package.license_file.is_none() {
+                self.push(
+                    Severity::Warning,
+                    &["package", "license"],
+                    "missing license or license-file".to_string(),
+                );
+            }
+            if package.description.is_none() {
+                self.push(
+                    Severity::Warning,
+                    &["package", "description"],
+                    "missing description".to_string(),
+                );
+            }
+        }
+
+        let sections = [
+            ("dependencies", &manifest.dependencies),
+            ("dev-dependencies", &manifest.dev_dependencies),
+            ("build-dependencies", &manifest.build_dependencies),
+        ];
+        let mut seen: Vec<(&str, &str)> = Vec::new();
+        for (section, deps) in sections {
+            for (name, dep) in deps.iter().flatten() {
+                let version = match dep {
+                    Dependency::Simple(version) => Some(version.as_str()),
+                    Dependency::Detailed(detail) => detail.version.as_deref(),
+                    _ => None,
+                };
+                if version.map(str::trim) == Some("*") {
+                    self.push(
+                        Severity::Warning,
+                        &[section, name],
+                        "wildcard version requirement".to_string(),
+                    );
+                }
+                if let Some((first, _)) = seen.iter().find(|(_, n)| n == name) {
+                    self.push(
+                        Severity::Info,
+                        &[section, name],
+                        format!("`{name}` is also declared in {}", pointer(&[first])),
+                    );
+                } else {
+                    seen.push((section, name));
+                }
+            }
+        }
     }
-    ret
 }
 
-pub fn router() -> Router {
-    Router::new().route("/5/manifest", post(p1))
-}
\ No newline at end of file
+async fn lint(
+    headers: HeaderMap,
+    bytes: Result<Bytes, BytesRejection>,
+) -> Result<Json<LintReport>, MyErr> {
+    let bytes = bytes?;
+    let content_type = check_content_type(&headers).ok_or(MyErr::ContentUnsupportedError)?;
+    let mut raw: toml::Table = parse_document(content_type, &bytes)?;
+
+    let mut report = LintReport {
+        findings: Vec::new(),
+    };
+    report.lint_raw(&mut raw);
+    match toml::Value::Table(raw).try_into::<Manifest>() {
+        Ok(manifest) => report.lint_manifest(&manifest),
+        Err(e) => report.push(Severity::Error, &[], e.message().to_string()),
+    }
+
+    Ok(Json(report))
+}
+
+// Re-serializes a manifest into the format asked for by `Accept`, TOML by
+// default. `Manifest` only validates it, the raw document is what gets written
+// so keys it does not model survive.
+async fn convert(
+    headers: HeaderMap,
+    bytes: Result<Bytes, BytesRejection>,
+) -> Result<Response, MyErr> {
+    let bytes = bytes?;
+    let content_type = check_content_type(&headers).ok_or(MyErr::ContentUnsupportedError)?;
+    let format = match check_accept(&headers).ok_or(MyErr::NotAcceptableError)? {
+        Format::Text => Format::Toml,
+        format => format,
+    };
+
+    parse_manifest(content_type, &bytes)?;
+    let raw: toml::Table = parse_document(content_type, &bytes)?;
+    render(format, &raw)
+}
+
+pub fn router(policy: KeywordPolicy) -> Router {
+    Router::new()
+        .route("/5/manifest", post(p1))
+        .route("/5/manifest/workspace", post(p2))
+        .route("/5/lint", post(lint))
+        .route("/5/convert", post(convert))
+        .with_state(MyState {
+            policy: Arc::new(policy),
+        })
+}
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn app() -> Router {
+        router(KeywordPolicy {
+            keywords: vec!["Christmas 2024".to_string()],
+            matching: KeywordMatch::Any,
+            case_sensitive: true,
+            workspace_keywords: None,
+        })
+    }
+
+    async fn send(req: Request<Body>) -> (StatusCode, Option<Value>) {
+        let res = app().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    fn manifest(content_type: &str, body: impl Into<Body>) -> Request<Body> {
+        Request::post("/5/manifest")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body.into())
+            .unwrap()
+    }
+
+    fn toml(package: &str) -> Request<Body> {
+        let body = format!("[package]\nname = \"a\"\nkeywords = [\"Christmas 2024\"]\n{package}");
+        manifest("application/toml", body)
+    }
+
+    async fn assert_error(req: Request<Body>, status: StatusCode, code: &str, message: &str) {
+        let (got, body) = send(req).await;
+        assert_eq!(got, status);
+        let body = body.expect("JSON error body");
+        assert_eq!(body["error"], code);
+        assert_eq!(body["message"], message);
+    }
+
+    #[tokio::test]
+    async fn metadata_error() {
+        let (status, body) = send(toml("")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(body, None);
+    }
+
+    #[tokio::test]
+    async fn invalid_error() {
+        let (status, body) = send(manifest("application/toml", "[package")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = body.expect("JSON error body");
+        assert_eq!(body["error"], "invalid_manifest");
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid manifest: "));
+    }
+
+    #[tokio::test]
+    async fn type_error_orders() {
+        assert_error(
+            toml("[package.metadata]\norders = \"x\"\n"),
+            StatusCode::BAD_REQUEST,
+            "type_mismatch",
+            "`package.metadata.orders` must be an array",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn type_error_metadata() {
+        assert_error(
+            toml("metadata = 1\n"),
+            StatusCode::BAD_REQUEST,
+            "type_mismatch",
+            "`package.metadata` must be a table",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn magic_error() {
+        let body = "[package]\nname = \"a\"\n[package.metadata]\norders = []\n";
+        assert_error(
+            manifest("application/toml", body),
+            StatusCode::BAD_REQUEST,
+            "magic_keyword_missing",
+            "Magic keyword not provided",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn content_unsupported_error() {
+        assert_error(
+            manifest("text/plain", ""),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_content_type",
+            "Unsupported content type",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn not_acceptable_error() {
+        let mut req = toml("[[package.metadata.orders]]\nitem = \"x\"\nquantity = 1\n");
+        req.headers_mut()
+            .insert(header::ACCEPT, "image/png".parse().unwrap());
+        assert_error(
+            req,
+            StatusCode::NOT_ACCEPTABLE,
+            "not_acceptable",
+            "Not acceptable",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn payload_too_large_error() {
+        // over the default 2MB body limit
+        let body = vec![b' '; 3 * 1024 * 1024];
+        assert_error(
+            manifest("application/toml", body),
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            "Payload too large",
+        )
+        .await;
+    }
+
+    async fn convert_via(content_type: &str, accept: &str, body: String) -> String {
+        let req = Request::post("/5/convert")
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ACCEPT, accept)
+            .body(Body::from(body))
+            .unwrap();
+        let res = app().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    const UNMODELED: &str = r#"
+cargo-features = ["edition2024"]
+
+[package]
+name = "a"
+version = "0.1.0"
+keywords = ["Christmas 2024"]
+frobnicate = { level = 3 }
+
+[package.metadata]
+orders = [{ item = "Toy car", quantity = 2 }]
+note = "keep me"
+
+[custom]
+flag = true
+"#;
+
+    #[tokio::test]
+    async fn convert_toml_yaml_toml() {
+        let yaml = convert_via(
+            "application/toml",
+            "application/yaml",
+            UNMODELED.to_string(),
+        )
+        .await;
+        let back = convert_via("application/yaml", "application/toml", yaml).await;
+        assert_eq!(
+            toml::from_str::<toml::Table>(&back).unwrap(),
+            toml::from_str::<toml::Table>(UNMODELED).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn convert_json_toml() {
+        let json =
+            serde_json::to_string(&toml::from_str::<toml::Table>(UNMODELED).unwrap()).unwrap();
+        let toml = convert_via("application/json", "application/toml", json.clone()).await;
+        assert_eq!(
+            toml::from_str::<toml::Table>(&toml).unwrap(),
+            serde_json::from_str::<toml::Table>(&json).unwrap()
+        );
+    }
+
+    async fn workspace(parts: &[(&str, &str)]) -> Value {
+        let mut body = String::new();
+        for (name, content) in parts {
+            body.push_str(&format!(
+                "--X\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{content}\r\n"
+            ));
+        }
+        body.push_str("--X--\r\n");
+        let req = Request::post("/5/manifest/workspace")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(Body::from(body))
+            .unwrap();
+        let (status, body) = send(req).await;
+        assert_eq!(status, StatusCode::OK);
+        body.unwrap()
+    }
+
+    const WORKSPACE: &str = r#"
+[workspace]
+members = ["crates/*"]
+
+[workspace.package]
+version = "1.2.3"
+edition = "2021"
+license = "MIT"
+keywords = ["Christmas 2024"]
+
+[workspace.dependencies]
+serde = { version = "1", features = ["derive"] }
+rand = "0.8"
+"#;
+
+    #[tokio::test]
+    async fn workspace_inheritance() {
+        let member = r#"
+[package]
+name = "a"
+version.workspace = true
+edition.workspace = true
+license = { workspace = true }
+keywords.workspace = true
+
+[dependencies]
+serde = { workspace = true, features = ["rc"], optional = true }
+rand.workspace = true
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+"#;
+        let report = workspace(&[("workspace", WORKSPACE), ("crates/a", member)]).await;
+        let member = &report["members"][0];
+        assert_eq!(member["error"], Value::Null);
+        assert_eq!(member["orders"][0]["item"], "Toy car");
+        let manifest = &member["manifest"];
+        assert_eq!(manifest["package"]["version"], "1.2.3");
+        assert_eq!(manifest["package"]["edition"], "2021");
+        assert_eq!(manifest["package"]["license"], "MIT");
+        assert_eq!(
+            manifest["dependencies"]["serde"],
+            serde_json::json!({ "version": "1", "features": ["derive", "rc"], "optional": true })
+        );
+        assert_eq!(manifest["dependencies"]["rand"]["version"], "0.8");
+    }
+
+    #[tokio::test]
+    async fn workspace_errors() {
+        let member = "[package]\nname = \"b\"\nkeywords.workspace = true\n";
+        let missing =
+            "[package]\nname = \"c\"\nkeywords.workspace = true\nhomepage.workspace = true\n";
+        let report = workspace(&[
+            ("workspace", WORKSPACE),
+            ("elsewhere/b", member),
+            ("crates/c", missing),
+        ])
+        .await;
+        assert_eq!(
+            report["members"][0]["error"],
+            "Invalid manifest: `elsewhere/b` is not in workspace.members"
+        );
+        assert_eq!(
+            report["members"][1]["error"],
+            "Invalid manifest: `package.homepage` is inherited but `workspace.package.homepage` is not set"
+        );
+    }
+
+    #[tokio::test]
+    async fn body_error() {
+        // a multipart body that ends before its closing boundary
+        let req = Request::post("/5/manifest/workspace")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(Body::from(
+                "--X\r\nContent-Disposition: form-data; name=\"workspace\"\r\n\r\n[workspace]",
+            ))
+            .unwrap();
+        let (status, body) = send(req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body.expect("JSON error body")["error"], "invalid_body");
+    }
+
+    #[tokio::test]
+    async fn query_error() {
+        let mut req = toml("[package.metadata]\norders = [{ item = \"Toy car\", quantity = 2 }]\n");
+        *req.uri_mut() = "/5/manifest?summary=yes".parse().unwrap();
+        let (status, body) = send(req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body.expect("JSON error body")["error"], "invalid_body");
+    }
+
+    #[tokio::test]
+    async fn accept_wildcards() {
+        let body = "[package.metadata]\norders = [{ item = \"Toy car\", quantity = 2 }]\n";
+        for (accept, content_type) in [
+            ("application/*", "application/json"),
+            ("text/*", "text/plain; charset=utf-8"),
+        ] {
+            let mut req = toml(body);
+            req.headers_mut()
+                .insert(header::ACCEPT, accept.parse().unwrap());
+            let res = app().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()[header::CONTENT_TYPE], content_type);
+        }
+    }
+}
@@ -1,14 +1,27 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
+    async_trait,
     body::Bytes,
-    extract::State,
-    http::{header, HeaderMap, StatusCode},
-    routing::post,
-    Router,
+    extract::{ConnectInfo, FromRequestParts, Query, State},
+    http::{header, request::Parts, HeaderMap, HeaderName, StatusCode},
+    routing::{get, post, put},
+    Json, Router,
 };
 use leaky_bucket::RateLimiter;
 use serde::{Deserialize, Serialize};
+use shuttle_runtime::SecretStore;
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    PgPool,
+};
+use tokio::time::timeout;
 
 type StdMutex<T> = std::sync::Mutex<T>;
 
@@ -31,6 +44,135 @@ impl Payload {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum Dimension {
+    Volume,
+    Mass,
+    Temperature,
+}
+
+// a value in this unit is `value * scale + offset` in the base unit of its
+// dimension (litres, kilograms, kelvin)
+struct Unit {
+    names: &'static [&'static str],
+    dimension: Dimension,
+    scale: f64,
+    offset: f64,
+}
+
+const fn unit(names: &'static [&'static str], dimension: Dimension, scale: f64) -> Unit {
+    Unit {
+        names,
+        dimension,
+        scale,
+        offset: 0.0,
+    }
+}
+
+const UNITS: &[Unit] = &[
+    unit(&["liters", "litres", "l"], Dimension::Volume, 1.0),
+    unit(
+        &["milliliters", "millilitres", "ml"],
+        Dimension::Volume,
+        0.001,
+    ),
+    unit(
+        &["cubic_meters", "cubic_metres", "m3"],
+        Dimension::Volume,
+        1000.0,
+    ),
+    unit(&["us_gallons", "gallons"], Dimension::Volume, 3.785_411_784),
+    unit(&["imperial_gallons"], Dimension::Volume, 4.546_09),
+    unit(&["us_quarts"], Dimension::Volume, 0.946_352_946),
+    unit(&["imperial_quarts"], Dimension::Volume, 1.136_522_5),
+    unit(&["us_pints"], Dimension::Volume, 0.473_176_473),
+    unit(
+        &["imperial_pints", "pints"],
+        Dimension::Volume,
+        0.568_261_25,
+    ),
+    unit(&["us_cups", "cups"], Dimension::Volume, 0.236_588_236_5),
+    unit(&["us_fluid_ounces"], Dimension::Volume, 0.029_573_529_562_5),
+    unit(
+        &["imperial_fluid_ounces"],
+        Dimension::Volume,
+        0.028_413_062_5,
+    ),
+    unit(&["kilograms", "kg"], Dimension::Mass, 1.0),
+    unit(&["grams", "g"], Dimension::Mass, 0.001),
+    unit(&["tonnes"], Dimension::Mass, 1000.0),
+    unit(&["pounds", "lb"], Dimension::Mass, 0.453_592_37),
+    unit(&["ounces", "oz"], Dimension::Mass, 0.028_349_523_125),
+    unit(&["stones"], Dimension::Mass, 6.350_293_18),
+    unit(&["us_tons", "short_tons"], Dimension::Mass, 907.184_74),
+    unit(
+        &["imperial_tons", "long_tons"],
+        Dimension::Mass,
+        1_016.046_908_8,
+    ),
+    unit(&["kelvin", "k"], Dimension::Temperature, 1.0),
+    Unit {
+        names: &["celsius", "c"],
+        dimension: Dimension::Temperature,
+        scale: 1.0,
+        offset: 273.15,
+    },
+    Unit {
+        names: &["fahrenheit", "f"],
+        dimension: Dimension::Temperature,
+        scale: 5.0 / 9.0,
+        offset: 459.67 * 5.0 / 9.0,
+    },
+];
+
+fn find_unit(name: &str) -> Option<&'static Unit> {
+    let name = name.to_lowercase();
+    UNITS.iter().find(|u| u.names.contains(&name.as_str()))
+}
+
+#[derive(Deserialize)]
+struct Conversion {
+    value: f64,
+    from: String,
+    to: String,
+    precision: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct Converted {
+    value: f64,
+    unit: String,
+}
+
+impl Conversion {
+    fn cal(self) -> Result<Converted, String> {
+        let from = find_unit(&self.from).ok_or(format!("unknown unit `{}`", self.from))?;
+        let to = find_unit(&self.to).ok_or(format!("unknown unit `{}`", self.to))?;
+        if from.dimension != to.dimension {
+            return Err(format!("cannot convert `{}` to `{}`", self.from, self.to));
+        }
+
+        let base = self.value * from.scale + from.offset;
+        let mut value = (base - to.offset) / to.scale;
+        if let Some(precision) = self.precision {
+            let factor = 10f64.powi(precision.min(15).into());
+            value = (value * factor).round() / factor;
+        }
+        Ok(Converted {
+            value,
+            unit: self.to,
+        })
+    }
+}
+
+// the single-key shape (`{"liters": 1.0}`) is kept as is
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Request {
+    Conversion(Conversion),
+    Legacy(Payload),
+}
+
 fn check_content_type(headers: &HeaderMap) -> bool {
     let content_type = if let Some(content_type) = headers.get(header::CONTENT_TYPE) {
         content_type
@@ -57,57 +199,839 @@ fn check_content_type(headers: &HeaderMap) -> bool {
 }
 
 #[derive(Clone)]
-struct MyState {
-    limiter: Arc<StdMutex<RateLimiter>>,
+enum KeySource {
+    Ip,
+    // falls back to the client IP when the header is missing
+    ApiKey,
+    Header(HeaderName),
 }
 
-async fn p1(State(s): State<MyState>, headers: HeaderMap, bytes: Bytes) -> (StatusCode, String) {
-    let mut ret = None;
-    if check_content_type(&headers) {
-        let payload = serde_json::from_slice::<Payload>(&bytes);
-        if let Ok(payload) = payload {
-            ret = Some((
-                StatusCode::OK,
-                serde_json::to_string(&payload.cal()).unwrap(),
-            ));
-        } else {
-            ret = Some((StatusCode::BAD_REQUEST, "".to_string()));
+pub struct Config {
+    key_source: KeySource,
+    max_clients: usize,
+    idle_timeout: Duration,
+    meter_conversions: bool,
+    persist: bool,
+    admin_token: Option<String>,
+    trusted_hops: usize,
+}
+impl Config {
+    pub fn from_secrets(secrets: &SecretStore) -> Self {
+        let key_source = match secrets.get("MILK_KEY_SOURCE").as_deref() {
+            Some("api-key") => KeySource::ApiKey,
+            Some(s) => match s.strip_prefix("header:").map(HeaderName::try_from) {
+                Some(Ok(name)) => KeySource::Header(name),
+                _ => KeySource::Ip,
+            },
+            None => KeySource::Ip,
+        };
+        let max_clients = secrets
+            .get("MILK_MAX_CLIENTS")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10_000);
+        let idle_timeout = secrets
+            .get("MILK_IDLE_SECS")
+            .and_then(|v| v.parse().ok())
+            .map_or(Duration::from_secs(60), Duration::from_secs);
+        let meter_conversions = secrets
+            .get("MILK_METER_CONVERSIONS")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(true);
+        let persist = secrets
+            .get("MILK_PERSIST")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+        let admin_token = secrets.get("MILK_ADMIN_TOKEN");
+        // proxies in front of us that append to X-Forwarded-For
+        let trusted_hops = secrets
+            .get("MILK_TRUSTED_HOPS")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
+
+        Self {
+            key_source,
+            max_clients,
+            idle_timeout,
+            meter_conversions,
+            persist,
+            admin_token,
+            trusted_hops,
         }
     }
+}
 
-    let n = if s.limiter.lock().unwrap().try_acquire(1) {
-        (StatusCode::OK, "Milk withdrawn\n".to_string())
-    } else {
-        (
+#[derive(Clone, Copy, Serialize)]
+struct Limits {
+    capacity: usize,
+    refill: usize,
+    interval_ms: u64,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            capacity: 5,
+            refill: 1,
+            interval_ms: 1000,
+        }
+    }
+}
+impl Limits {
+    fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    // fair, so `?wait` requests are served in arrival order and a waiting
+    // queue makes `try_acquire` fail rather than jump ahead of it
+    fn limiter(&self, initial: usize) -> Arc<RateLimiter> {
+        let limiter = RateLimiter::builder()
+            .initial(initial)
+            .max(self.capacity)
+            .interval(self.interval())
+            .refill(self.refill)
+            .fair(true)
+            .build();
+        Arc::new(limiter)
+    }
+
+    // `tokens` plus the whole refills that landed in `elapsed`
+    fn refilled(&self, tokens: usize, elapsed: Duration) -> usize {
+        let refills = (elapsed.as_millis() / self.interval().as_millis()) as usize;
+        tokens
+            .saturating_add(refills.saturating_mul(self.refill))
+            .min(self.capacity)
+    }
+}
+
+struct Bucket {
+    limiter: Arc<RateLimiter>,
+    last_used: Instant,
+    // refills land on whole intervals from here
+    created: Instant,
+}
+impl Bucket {
+    fn new(limiter: Arc<RateLimiter>, now: Instant) -> Self {
+        Self {
+            limiter,
+            last_used: now,
+            created: now,
+        }
+    }
+
+    fn last_refill(&self, now: Instant) -> Option<DateTime<Utc>> {
+        let interval = self.limiter.interval();
+        let elapsed = now.duration_since(self.created);
+        let refills = (elapsed.as_millis() / interval.as_millis().max(1)) as u32;
+        if refills == 0 {
+            return None;
+        }
+        Some(Utc::now() - (elapsed - interval * refills))
+    }
+}
+
+struct Buckets {
+    map: HashMap<String, Bucket>,
+    // balance and last use of evicted buckets that were not full yet, until
+    // they would have refilled
+    parked: HashMap<String, (usize, Instant)>,
+    max_clients: usize,
+    idle_timeout: Duration,
+    limits: Limits,
+}
+impl Buckets {
+    fn get(&mut self, key: &str) -> &Arc<RateLimiter> {
+        let now = Instant::now();
+        if !self.map.contains_key(key) {
+            self.evict(now);
+            let tokens = self
+                .parked_balance(key, now)
+                .unwrap_or(self.limits.capacity);
+            self.parked.remove(key);
+            let limiter = self.limits.limiter(tokens);
+            self.map.insert(key.to_string(), Bucket::new(limiter, now));
+        }
+        let bucket = self.map.get_mut(key).unwrap();
+        bucket.last_used = now;
+        &bucket.limiter
+    }
+
+    // a bucket created meanwhile is more recent than the stored one
+    fn restore(&mut self, key: &str, tokens: usize, updated_at: DateTime<Utc>) {
+        let now = Instant::now();
+        if !self.map.contains_key(key) {
+            // credit the refills it missed while not in memory
+            let elapsed = (Utc::now() - updated_at).to_std().unwrap_or_default();
+            let tokens = self.limits.refilled(tokens, elapsed);
+            self.evict(now);
+            self.parked.remove(key);
+            let limiter = self.limits.limiter(tokens);
+            self.map.insert(key.to_string(), Bucket::new(limiter, now));
+        }
+    }
+
+    // A limiter cannot be reconfigured in place, so swap in one built from the
+    // current limits that starts at `tokens(balance)`. Returns the new balances.
+    fn rebuild(
+        &mut self,
+        key: Option<&str>,
+        tokens: impl Fn(usize) -> usize,
+    ) -> Vec<(String, usize)> {
+        let now = Instant::now();
+        let limits = self.limits;
+        self.parked
+            .iter_mut()
+            .filter(|(k, _)| key.is_none_or(|key| key == k.as_str()))
+            .for_each(|(_, (balance, _))| *balance = tokens(*balance));
+        self.map
+            .iter_mut()
+            .filter(|(k, _)| key.is_none_or(|key| key == k.as_str()))
+            .map(|(k, bucket)| {
+                let balance = tokens(current_balance(&bucket.limiter)).min(limits.capacity);
+                bucket.limiter = limits.limiter(balance);
+                bucket.created = now;
+                (k.clone(), balance)
+            })
+            .collect()
+    }
+
+    // what a parked bucket would hold by `now`
+    fn parked_balance(&self, key: &str, now: Instant) -> Option<usize> {
+        let &(tokens, last_used) = self.parked.get(key)?;
+        Some(self.limits.refilled(tokens, now.duration_since(last_used)))
+    }
+
+    // Drop idle buckets, then least recently used ones until there is room for
+    // one more. A bucket that is not full is parked rather than forgotten, or
+    // it would come back full and skip the limiter.
+    fn evict(&mut self, now: Instant) {
+        let limits = self.limits;
+        self.parked.retain(|_, &mut (tokens, at)| {
+            limits.refilled(tokens, now.duration_since(at)) < limits.capacity
+        });
+        let idle: Vec<_> = self
+            .map
+            .iter()
+            .filter(|(_, b)| now.duration_since(b.last_used) >= self.idle_timeout)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in idle {
+            self.park(&key);
+        }
+        while !self.map.is_empty() && self.map.len() >= self.max_clients {
+            let lru = self
+                .map
+                .iter()
+                .min_by_key(|(_, b)| b.last_used)
+                .map(|(k, _)| k.clone())
+                .unwrap();
+            self.park(&lru);
+        }
+    }
+
+    fn park(&mut self, key: &str) {
+        let Some(bucket) = self.map.remove(key) else {
+            return;
+        };
+        let tokens = current_balance(&bucket.limiter);
+        if tokens < bucket.limiter.max() {
+            self.parked
+                .insert(key.to_string(), (tokens, bucket.last_used));
+        }
+    }
+}
+
+// the balance is only brought up to date by a slow path acquire, asking for
+// more than can ever be there takes that path without drawing anything
+fn current_balance(limiter: &RateLimiter) -> usize {
+    limiter.try_acquire(usize::MAX);
+    limiter.balance().min(limiter.max())
+}
+
+// draft-ietf-httpapi-ratelimit-headers, the limiter does not expose its next
+// refill deadline so reset and retry-after are upper bounds from its config
+fn rate_limit_headers(limiter: &RateLimiter, throttled: bool) -> HeaderMap {
+    let max = limiter.max();
+    let remaining = limiter.balance().min(max);
+    let interval = limiter.interval();
+    let refills = (max - remaining).div_ceil(limiter.refill().max(1));
+    let reset = interval.as_secs_f64() * refills as f64;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("ratelimit-limit", max.into());
+    headers.insert("ratelimit-remaining", remaining.into());
+    headers.insert("ratelimit-reset", (reset.ceil() as u64).into());
+    if throttled {
+        let retry_after = interval.as_secs_f64().ceil().max(1.0) as u64;
+        headers.insert(header::RETRY_AFTER, retry_after.into());
+    }
+    headers
+}
+
+#[derive(Clone)]
+struct MyState {
+    buckets: Arc<StdMutex<Buckets>>,
+    key_source: KeySource,
+    meter_conversions: bool,
+    // bucket balances outlive restarts when set, see `load` and `save`
+    store: Option<PgPool>,
+    admin_token: Option<Arc<str>>,
+    trusted_hops: usize,
+}
+
+// The store is best effort, a database error leaves the in-memory bucket as is.
+async fn load(s: &MyState, key: &str) {
+    let Some(pool) = &s.store else {
+        return;
+    };
+    if s.buckets.lock().unwrap().map.contains_key(key) {
+        return;
+    }
+    let row: Option<(i32, DateTime<Utc>)> =
+        sqlx::query_as("SELECT tokens, updated_at FROM milk_buckets WHERE key = $1")
+            .bind(key)
+            .fetch_optional(pool)
+            .await
+            .ok()
+            .flatten();
+    if let Some((tokens, updated_at)) = row {
+        let tokens = tokens.max(0) as usize;
+        s.buckets.lock().unwrap().restore(key, tokens, updated_at);
+    }
+}
+
+async fn save(s: &MyState, key: &str, tokens: usize) {
+    let Some(pool) = &s.store else {
+        return;
+    };
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO milk_buckets (key, tokens, updated_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (key) DO UPDATE SET tokens = $2, updated_at = $3
+        "#,
+    )
+    .bind(key)
+    .bind(i32::try_from(tokens).unwrap_or(i32::MAX))
+    .bind(Utc::now())
+    .execute(pool)
+    .await;
+}
+
+struct ClientKey(String);
+
+#[async_trait]
+impl FromRequestParts<MyState> for ClientKey {
+    type Rejection = Infallible;
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &MyState,
+    ) -> Result<Self, Self::Rejection> {
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let ip = || {
+            // Everything left of what our own proxies appended is whatever the
+            // client sent, so the entry added by the outermost trusted hop is
+            // the first one that can be believed.
+            let forwarded = parts
+                .headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .map(str::trim)
+                .collect::<Vec<_>>();
+            let forwarded = (state.trusted_hops > 0 && !forwarded.is_empty())
+                .then(|| forwarded[forwarded.len().saturating_sub(state.trusted_hops)].to_string());
+            // ConnectInfo is only there when served with `into_make_service_with_connect_info`
+            forwarded
+                .or_else(|| {
+                    parts
+                        .extensions
+                        .get::<ConnectInfo<SocketAddr>>()
+                        .map(|ConnectInfo(addr)| addr.ip().to_string())
+                })
+                .unwrap_or_else(|| "unknown".to_string())
+        };
+
+        let key = match &state.key_source {
+            KeySource::Ip => format!("ip:{}", ip()),
+            KeySource::ApiKey => match header("x-api-key") {
+                Some(key) => format!("key:{key}"),
+                None => format!("ip:{}", ip()),
+            },
+            KeySource::Header(name) => match header(name.as_str()) {
+                Some(v) => format!("header:{v}"),
+                None => format!("ip:{}", ip()),
+            },
+        };
+        Ok(Self(key))
+    }
+}
+
+fn convert(bytes: &[u8]) -> Result<String, String> {
+    match serde_json::from_slice::<Request>(bytes) {
+        Ok(Request::Legacy(payload)) => Ok(serde_json::to_string(&payload.cal()).unwrap()),
+        Ok(Request::Conversion(c)) => c.cal().map(|c| serde_json::to_string(&c).unwrap()),
+        Err(_) => Err("".to_string()),
+    }
+}
+
+const MAX_WAIT: Duration = Duration::from_secs(30);
+
+// "2s", "500ms" or a bare number of seconds, capped at MAX_WAIT
+fn parse_wait(s: &str) -> Option<Duration> {
+    let wait = match s.strip_suffix("ms") {
+        Some(ms) => Duration::from_millis(ms.parse().ok()?),
+        None => Duration::try_from_secs_f64(s.strip_suffix('s').unwrap_or(s).parse().ok()?).ok()?,
+    };
+    Some(wait.min(MAX_WAIT))
+}
+
+#[derive(Deserialize)]
+struct MilkQuery {
+    wait: Option<String>,
+}
+
+// A JSON body is a conversion, anything else withdraws milk.
+// - withdrawals always draw a token, 429 "No milk available" when empty
+// - invalid conversions are a 400 and never touch the bucket
+// - valid conversions draw a token only when MILK_METER_CONVERSIONS is on
+//   (the default), and are a 429 "Conversion throttled" when empty
+// - with `?wait=` the request queues for its token until the deadline instead
+//   of failing right away
+async fn p1(
+    State(s): State<MyState>,
+    ClientKey(key): ClientKey,
+    Query(q): Query<MilkQuery>,
+    headers: HeaderMap,
+    bytes: Bytes,
+) -> (StatusCode, HeaderMap, String) {
+    let wait = q
+        .wait
+        .as_deref()
+        .map(|w| parse_wait(w).ok_or(format!("Invalid wait {w:?}\n")))
+        .transpose();
+    let conversion = check_content_type(&headers).then(|| convert(&bytes));
+    let metered = wait.is_ok()
+        && match &conversion {
+            None => true,
+            Some(Ok(_)) => s.meter_conversions,
+            Some(Err(_)) => false,
+        };
+
+    load(&s, &key).await;
+    // waiters hold on to their limiter, so one caught by a `PUT /9/config`
+    // is still served by the old limits
+    let limiter = s.buckets.lock().unwrap().get(&key).clone();
+    let acquired = match wait {
+        Ok(Some(wait)) if metered => timeout(wait, limiter.acquire_one()).await.is_ok(),
+        _ => metered && limiter.try_acquire(1),
+    };
+    let limit_headers = rate_limit_headers(&limiter, metered && !acquired);
+    if acquired {
+        save(&s, &key, current_balance(&limiter)).await;
+    }
+
+    if let Err(e) = wait {
+        return (StatusCode::BAD_REQUEST, limit_headers, e);
+    }
+    let (status, body) = match conversion {
+        None if acquired => (StatusCode::OK, "Milk withdrawn\n".to_string()),
+        None => (
             StatusCode::TOO_MANY_REQUESTS,
             "No milk available\n".to_string(),
-        )
+        ),
+        Some(Err(e)) => (StatusCode::BAD_REQUEST, e),
+        Some(Ok(body)) if acquired || !metered => (StatusCode::OK, body),
+        Some(Ok(_)) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Conversion throttled\n".to_string(),
+        ),
     };
+    (status, limit_headers, body)
+}
+#[derive(Deserialize)]
+struct RefillQuery {
+    key: Option<String>,
+}
 
-    if let Some(ret) = ret {
-        ret
-    } else {
-        n
+#[derive(Deserialize)]
+struct RefillBody {
+    amount: usize,
+}
+
+// Without a body a missing bucket is recreated full, so dropping it is a
+// refill. `{"amount": n}` tops the buckets up by n tokens instead.
+async fn p2(
+    State(s): State<MyState>,
+    Query(q): Query<RefillQuery>,
+    bytes: Bytes,
+) -> Result<(), (StatusCode, String)> {
+    let key = q.key.as_deref();
+    if bytes.is_empty() {
+        {
+            let mut buckets = s.buckets.lock().unwrap();
+            match key {
+                Some(key) => {
+                    buckets.map.remove(key);
+                    buckets.parked.remove(key);
+                }
+                None => {
+                    buckets.map.clear();
+                    buckets.parked.clear();
+                }
+            }
+        }
+        if let Some(pool) = &s.store {
+            let query = match key {
+                Some(key) => sqlx::query("DELETE FROM milk_buckets WHERE key = $1").bind(key),
+                None => sqlx::query("DELETE FROM milk_buckets"),
+            };
+            let _ = query.execute(pool).await;
+        }
+        return Ok(());
     }
+
+    let amount = serde_json::from_slice::<RefillBody>(&bytes)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+        .amount;
+    let (capacity, balances) = {
+        let mut buckets = s.buckets.lock().unwrap();
+        let balances = buckets.rebuild(key, |balance| balance.saturating_add(amount));
+        (buckets.limits.capacity, balances)
+    };
+    if let Some(pool) = &s.store {
+        // rows of buckets that are not in memory keep their updated_at, so
+        // `load` still credits the refills they missed
+        let amount = i64::try_from(amount).unwrap_or(i64::MAX);
+        let capacity = i64::try_from(capacity).unwrap_or(i64::MAX);
+        let query = match key {
+            Some(key) => sqlx::query(
+                "UPDATE milk_buckets SET tokens = LEAST(tokens + $1, $2) WHERE key = $3",
+            )
+            .bind(amount)
+            .bind(capacity)
+            .bind(key),
+            None => sqlx::query("UPDATE milk_buckets SET tokens = LEAST(tokens + $1, $2)")
+                .bind(amount)
+                .bind(capacity),
+        };
+        let _ = query.execute(pool).await;
+    }
+    for (key, balance) in balances {
+        save(&s, &key, balance).await;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct LimitsUpdate {
+    capacity: Option<usize>,
+    refill: Option<usize>,
+    interval_ms: Option<u64>,
+}
+
+// Needs `Authorization: Bearer <MILK_ADMIN_TOKEN>` and is forbidden when no
+// token is configured. Fields left out keep their current value.
+async fn put_config(
+    State(s): State<MyState>,
+    headers: HeaderMap,
+    bytes: Bytes,
+) -> Result<Json<Limits>, (StatusCode, String)> {
+    let Some(token) = &s.admin_token else {
+        return Err((StatusCode::FORBIDDEN, "".to_string()));
+    };
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if bearer != Some(&**token) {
+        return Err((StatusCode::UNAUTHORIZED, "".to_string()));
+    }
+    let update = serde_json::from_slice::<LimitsUpdate>(&bytes)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let mut buckets = s.buckets.lock().unwrap();
+    let limits = Limits {
+        capacity: update.capacity.unwrap_or(buckets.limits.capacity),
+        refill: update.refill.unwrap_or(buckets.limits.refill),
+        interval_ms: update.interval_ms.unwrap_or(buckets.limits.interval_ms),
+    };
+    if limits.capacity == 0 || limits.refill == 0 || limits.interval_ms == 0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "capacity, refill and interval_ms must be positive".to_string(),
+        ));
+    }
+    // an interval longer than the idle timeout is fine, `Buckets::evict` parks
+    // buckets that have not refilled yet
+    buckets.limits = limits;
+    // existing buckets keep their balance, cut down to a smaller capacity
+    buckets.rebuild(None, |balance| balance);
+    Ok(Json(limits))
 }
-async fn p2(State(s): State<MyState>) {
-    *s.limiter.lock().unwrap() = get_default_limiter();
+
+#[derive(Deserialize)]
+struct StatusQuery {
+    key: Option<String>,
 }
 
-fn get_default_limiter() -> RateLimiter {
-    RateLimiter::builder()
-        .initial(5)
-        .max(5)
-        .interval(Duration::from_secs(1))
-        .refill(1)
-        .build()
+#[derive(Serialize)]
+struct Status {
+    key: String,
+    tokens: usize,
+    #[serde(flatten)]
+    limits: Limits,
+    // None until the bucket has been through a whole interval
+    last_refill: Option<DateTime<Utc>>,
 }
 
-pub fn router() -> Router {
+// defaults to the caller's own bucket, a key nobody used yet reads as full
+async fn status(
+    State(s): State<MyState>,
+    ClientKey(client): ClientKey,
+    Query(q): Query<StatusQuery>,
+) -> Json<Status> {
+    let key = q.key.unwrap_or(client);
+    load(&s, &key).await;
+
+    let buckets = s.buckets.lock().unwrap();
+    let limits = buckets.limits;
+    let (tokens, last_refill) = match buckets.map.get(&key) {
+        Some(bucket) => (
+            current_balance(&bucket.limiter),
+            bucket.last_refill(Instant::now()),
+        ),
+        None => (
+            buckets
+                .parked_balance(&key, Instant::now())
+                .unwrap_or(limits.capacity),
+            None,
+        ),
+    };
+    Json(Status {
+        key,
+        tokens,
+        limits,
+        last_refill,
+    })
+}
+
+pub fn router(config: Config, pool: PgPool) -> Router {
+    let buckets = Buckets {
+        map: HashMap::new(),
+        parked: HashMap::new(),
+        max_clients: config.max_clients,
+        idle_timeout: config.idle_timeout,
+        limits: Limits::default(),
+    };
     Router::new()
         .route("/9/milk", post(p1))
         .route("/9/refill", post(p2))
+        .route("/9/status", get(status))
+        .route("/9/config", put(put_config))
         .with_state(MyState {
-            limiter: Arc::new(StdMutex::new(get_default_limiter())),
+            buckets: Arc::new(StdMutex::new(buckets)),
+            key_source: config.key_source,
+            meter_conversions: config.meter_conversions,
+            store: config.persist.then_some(pool),
+            admin_token: config.admin_token.map(Arc::from),
+            trusted_hops: config.trusted_hops,
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn config(meter_conversions: bool) -> Config {
+        Config {
+            key_source: KeySource::Ip,
+            max_clients: 10,
+            idle_timeout: Duration::from_secs(60),
+            meter_conversions,
+            persist: false,
+            admin_token: None,
+            trusted_hops: 1,
+        }
+    }
+
+    fn app_with(config: Config) -> Router {
+        router(config, PgPool::connect_lazy("postgres://unused").unwrap())
+    }
+
+    fn app(meter_conversions: bool) -> Router {
+        app_with(config(meter_conversions))
+    }
+
+    async fn send(app: &Router, req: Request<Body>) -> (StatusCode, String) {
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn withdraw() -> Request<Body> {
+        Request::post("/9/milk").body(Body::empty()).unwrap()
+    }
+
+    fn withdraw_as(client: &'static str) -> Request<Body> {
+        Request::post("/9/milk")
+            .header("x-forwarded-for", client)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn conversion(body: &'static str) -> Request<Body> {
+        Request::post("/9/milk")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn tokens(app: &Router) -> u64 {
+        let req = Request::get("/9/status").body(Body::empty()).unwrap();
+        let (_, body) = send(app, req).await;
+        let status: serde_json::Value = serde_json::from_str(&body).unwrap();
+        status["tokens"].as_u64().unwrap()
+    }
+
+    async fn drain(app: &Router) {
+        for _ in 0..Limits::default().capacity {
+            assert_eq!(send(app, withdraw()).await.0, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn withdrawal() {
+        for meter in [true, false] {
+            let app = app(meter);
+            assert_eq!(
+                send(&app, withdraw()).await,
+                (StatusCode::OK, "Milk withdrawn\n".to_string())
+            );
+            assert_eq!(tokens(&app).await, 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn withdrawal_throttled() {
+        for meter in [true, false] {
+            let app = app(meter);
+            drain(&app).await;
+            assert_eq!(
+                send(&app, withdraw()).await,
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "No milk available\n".to_string()
+                )
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_conversion_draws_nothing() {
+        for meter in [true, false] {
+            let app = app(meter);
+            let (status, _) = send(&app, conversion("{bad")).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(tokens(&app).await, 5);
+        }
+    }
+
+    #[tokio::test]
+    async fn metered_conversion() {
+        let app = app(true);
+        assert_eq!(
+            send(&app, conversion(r#"{"liters":5}"#)).await,
+            (StatusCode::OK, r#"{"gallons":1.3208603}"#.to_string())
+        );
+        assert_eq!(tokens(&app).await, 4);
+    }
+
+    #[tokio::test]
+    async fn metered_conversion_throttled() {
+        let app = app(true);
+        drain(&app).await;
+        assert_eq!(
+            send(&app, conversion(r#"{"liters":5}"#)).await,
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Conversion throttled\n".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn unmetered_conversion() {
+        let app = app(false);
+        assert_eq!(
+            send(&app, conversion(r#"{"liters":5}"#)).await,
+            (StatusCode::OK, r#"{"gallons":1.3208603}"#.to_string())
+        );
+        assert_eq!(tokens(&app).await, 5);
+
+        drain(&app).await;
+        assert_eq!(
+            send(&app, conversion(r#"{"liters":5}"#)).await,
+            (StatusCode::OK, r#"{"gallons":1.3208603}"#.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn evicted_bucket_stays_drained() {
+        // idle eviction and least recently used eviction
+        for (idle_timeout, max_clients) in [(Duration::ZERO, 10), (Duration::from_secs(60), 1)] {
+            let app = app_with(Config {
+                idle_timeout,
+                max_clients,
+                ..config(true)
+            });
+            for _ in 0..Limits::default().capacity {
+                assert_eq!(send(&app, withdraw_as("1.1.1.1")).await.0, StatusCode::OK);
+            }
+            assert_eq!(
+                send(&app, withdraw_as("1.1.1.1")).await.0,
+                StatusCode::TOO_MANY_REQUESTS
+            );
+
+            assert_eq!(send(&app, withdraw_as("2.2.2.2")).await.0, StatusCode::OK);
+            assert_eq!(
+                send(&app, withdraw_as("1.1.1.1")).await.0,
+                StatusCode::TOO_MANY_REQUESTS
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_refill_survives_idle_eviction() {
+        let app = app_with(Config {
+            idle_timeout: Duration::ZERO,
+            admin_token: Some("secret".to_string()),
+            ..config(true)
+        });
+        let req = Request::put("/9/config")
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::from(r#"{"interval_ms":3600000}"#))
+            .unwrap();
+        assert_eq!(send(&app, req).await.0, StatusCode::OK);
+
+        for _ in 0..Limits::default().capacity {
+            assert_eq!(send(&app, withdraw_as("1.1.1.1")).await.0, StatusCode::OK);
+        }
+        assert_eq!(send(&app, withdraw_as("2.2.2.2")).await.0, StatusCode::OK);
+        assert_eq!(
+            send(&app, withdraw_as("1.1.1.1")).await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use axum::Router;
+use shuttle_runtime::SecretStore;
 use sqlx::PgPool;
 
 mod day0;
@@ -14,6 +15,8 @@ mod day23;
 async fn main(
     #[shuttle_shared_db::Postgres] 
     pool: PgPool,
+    #[shuttle_runtime::Secrets]
+    secrets: SecretStore,
 ) -> shuttle_axum::ShuttleAxum {
     sqlx::migrate!()
         .run(&pool)
@@ -22,8 +25,8 @@ async fn main(
 
     let d0 = day0::router();
     let d2 = day2::router();
-    let d5 = day5::router();
-    let d9 = day9::router();
+    let d5 = day5::router(day5::KeywordPolicy::from_secrets(&secrets));
+    let d9 = day9::router(day9::Config::from_secrets(&secrets), pool.clone());
     let d12 = day12::router();
     let d16 = day16::router();
     let d19 = day19::router(pool);